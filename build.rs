@@ -2,6 +2,7 @@ use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
     // Put the linker script somewhere the linker can find it
@@ -17,7 +18,21 @@ fn main() {
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
+    // Typed by Action::TypeVersion
+    let git = Command::new("git")
+        .args(&["describe", "--always", "--dirty"])
+        .output();
+    let hash = match git {
+        Ok(ref output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => "unknown".to_string(),
+    };
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=memory-release.x");
     println!("cargo:rerun-if-changed=memory-debug.x");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
 }
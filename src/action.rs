@@ -1,4 +1,6 @@
 use keycodes::KeyCode;
+use steno::StenoKey;
+use unicode::UnicodeMode;
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, PartialEq)]
@@ -6,40 +8,88 @@ pub enum Action {
     Nop,
     Transparent,
 
-    Key(KeyCode), // = 0x10
+    Key(KeyCode),                 // = 0x10
+    Shifted(KeyCode),             // key with Shift held
+    ModTap(KeyCode, KeyCode),     // modifier when held, key when tapped
+    SpaceCadet(KeyCode, KeyCode), // modifier when held, shifted key when tapped alone
+    TapDance(u8),                 // index into layout::TAP_DANCES
+    OneShot(KeyCode),             // modifier applied to the next key press
+    CapsWord,                     // shift letters until the end of the word
+    GraveEscape,                  // Escape, or ` while Shift or Meta is held
+    Turbo(KeyCode, u8),           // repeats key while held, toggling every n scans
+    Unicode(u8),                  // index into layout::UNICODE
+    SetUnicodeMode(UnicodeMode),  // input method used by Unicode
+    GamingMode,                   // toggles plain keys without Meta and dual roles
+    PrivacyMode,                  // toggles dark LEDs and no debug output
+    SwapHands,                    // mirrors the keyboard while held
+    SwapHandsOneShot,             // mirrors the next key press when tapped
+    Leader,                       // start of a sequence from layout::LEADER_SEQUENCES
+    Compose,                      // next two keys type one of layout::COMPOSE_SEQUENCES
+    KeyLock,                      // keeps the next key held until it's pressed again
+    Steno(StenoKey),              // part of a chord, sent once all keys are released
+    Macro(u8),                    // index into layout::MACROS
+    TypeVersion,                  // types macros::VERSION
+    DynamicMacroRecord,           // starts/stops recording
+    DynamicMacroPlay,
+    AutoShiftToggle,
 
-    LayerMomentary(u8), // = 0x20,
+    LayerMomentary(u8),    // = 0x20,
+    LayerTap(u8, KeyCode), // layer while held, key when tapped alone
     LayerToggle(u8),
     LayerOn(u8),
     LayerOff(u8),
+    LayerOneShot(u8), // active until the next key press is released
+    LayerLock,        // keeps held momentary layers on after their release
+    DefaultLayer(u8), // replaces the default layer, saved across power cycles
+    SetKeymap(u8),    // index into layout::KEYMAPS, saved across power cycles
 
     LedOn, // = 0x30,
     LedOff,
     LedToggle,
     LedNextTheme,
+    LedRandomTheme, // picks a theme other than the current one
     LedNextBrightness,
+    LedPreviousBrightness,
     LedNextAnimationSpeed,
+    LedNextAnimation, // cycles through the animations from animation::Animation
+    LedBattery,       // shows the battery level on the number row
+    LedWpm,           // toggles showing the typing speed on the number row
+    LedHeatmap,       // toggles coloring the keys by how often they're pressed
+    LedClock,         // toggles showing the time as a binary clock
     LedTheme(u8),
+    LedBrightness(u8), // jumps to a brightness level
+    LedSpeed(u8),      // jumps to an animation speed
+    LedAnimation(u8),  // picks an animation from animation::Animation, 0 is off
+    LedTestPattern,    // lights up every key in every color, one after the other
 
     //Bluetooth = 0x40,
     BtOn,
     BtOff,
     BtSaveHost(u8),
+    BtExitPairing,
     BtConnectHost(u8),
     BtDeleteHost(u8),
+    BtDeleteAllHosts,
     BtBroadcast,
     BtCompatibilityMode(bool),
     BtToggleCompatibilityMode,
-    BtHostListQuery, // TODO: remove? this shouldn't really be here
+    BtHostListQuery,        // TODO: remove? this shouldn't really be here
+    BtProfile(u8, KeyCode), // connects to host n when held, key when tapped
+    BtPair(KeyCode),        // pairing with a new host when held, key when tapped
 }
 
 // Allow auto-conversion of KeyCodes to Action for nicer layout formatting
-// and drop commas
+// and drop commas. Each row of the key matrix goes into [], so rows of the
+// wrong length don't compile.
 macro_rules! layout {
-    ( $( $e: expr )* ) => {
+    ( $( [ $( $e: expr )* ] )* ) => {
         [
             $(
-                $e.to_action(),
+                [
+                    $(
+                        $e.to_action(),
+                    )*
+                ],
             )*
         ]
     };
@@ -0,0 +1,341 @@
+use color::{blend, Hsv};
+use framebuffer::{scale, Framebuffer, Rgb, KEYS};
+use keycodes::{KEY_POSITIONS, NO_KEYS};
+use keymatrix::COLUMNS;
+use layout::{
+    ANIMATION_COLOR, ANIMATION_INTERVAL, BOOT_SPLASH, GRADIENT_COLOR, REACTIVE_FADE_FRAMES,
+    SPLASH_VERSION_TIME, TEST_PATTERN_TIME,
+};
+use led::LedMode;
+
+/// Effects rendered on the main MCU, on top of the LED controller's themes.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Animation {
+    Off,
+    /// all colors sweeping across the keyboard
+    Rainbow,
+    /// ANIMATION_COLOR fading in and out
+    Breathing,
+    /// a band of ANIMATION_COLOR running from left to right
+    Wave,
+    /// pressed keys light up and fade out
+    Reactive,
+    /// rings spreading out from pressed keys
+    Ripple,
+    /// all keys going through all colors together
+    RainbowCycle,
+    /// ANIMATION_COLOR blending into GRADIENT_COLOR across the keyboard and
+    /// back, slowly drifting from left to right
+    Gradient,
+}
+
+impl Animation {
+    pub fn next(self) -> Animation {
+        match self {
+            Animation::Off => Animation::Rainbow,
+            Animation::Rainbow => Animation::Breathing,
+            Animation::Breathing => Animation::Wave,
+            Animation::Wave => Animation::Reactive,
+            Animation::Reactive => Animation::Ripple,
+            Animation::Ripple => Animation::RainbowCycle,
+            Animation::RainbowCycle => Animation::Gradient,
+            Animation::Gradient => Animation::Off,
+        }
+    }
+
+    /// The animation `id` steps after Off, as used by Action::LedAnimation.
+    pub fn from_id(id: u8) -> Animation {
+        let mut animation = Animation::Off;
+        for _ in 0..id {
+            animation = animation.next();
+            if animation == Animation::Off {
+                break;
+            }
+        }
+        animation
+    }
+}
+
+const MAX_RIPPLES: usize = 4;
+const NO_RIPPLE: u8 = 0xff;
+// Ripples grow by RIPPLE_SPEED quarter keys per frame until they're
+// RIPPLE_RADIUS wide, their ring is RIPPLE_WIDTH thick.
+const RIPPLE_SPEED: u8 = 2;
+const RIPPLE_RADIUS: u8 = 64;
+const RIPPLE_WIDTH: usize = 4;
+
+/// Renders a frame of the current animation every ANIMATION_INTERVAL scans.
+pub struct Animator {
+    pub animation: Animation,
+    frame: u32,
+    next_frame: u32,
+    // brightness of each key for Reactive, lowered every frame
+    fade: [u8; KEYS],
+    // (key, radius in quarter keys) of the latest presses, NO_RIPPLE marks a
+    // free slot
+    ripples: [(u8, u8); MAX_RIPPLES],
+    next_ripple: usize,
+}
+
+impl Animator {
+    pub const fn new() -> Animator {
+        Animator {
+            animation: Animation::Off,
+            frame: 0,
+            next_frame: 0,
+            fade: [0; KEYS],
+            ripples: [(NO_RIPPLE, 0); MAX_RIPPLES],
+            next_ripple: 0,
+        }
+    }
+
+    /// Starts the reactive effects of `key`, replacing the oldest ripple.
+    pub fn key_pressed(&mut self, key: usize) {
+        if self.animation == Animation::Off || key >= KEYS {
+            return;
+        }
+        self.fade[key] = 0xff;
+        self.ripples[self.next_ripple] = (key as u8, 0);
+        self.next_ripple = (self.next_ripple + 1) % MAX_RIPPLES;
+    }
+
+    /// Renders into `framebuffer` if a frame is due, returns whether it did.
+    pub fn tick(&mut self, now: u32, framebuffer: &mut Framebuffer) -> bool {
+        if self.animation == Animation::Off || (now.wrapping_sub(self.next_frame) as i32) < 0 {
+            return false;
+        }
+        self.next_frame = now.wrapping_add(ANIMATION_INTERVAL);
+        self.frame = self.frame.wrapping_add(1);
+
+        for index in 0..KEYS {
+            let color = self.render(index);
+            framebuffer.set_index_now(index, color, LedMode::On);
+        }
+        self.fade_out();
+        true
+    }
+
+    fn fade_out(&mut self) {
+        let step = 0xff / REACTIVE_FADE_FRAMES.max(1);
+        for level in self.fade.iter_mut() {
+            *level = level.saturating_sub(step);
+        }
+        for ripple in self.ripples.iter_mut().filter(|r| r.0 != NO_RIPPLE) {
+            ripple.1 += RIPPLE_SPEED;
+            if ripple.1 >= RIPPLE_RADIUS {
+                ripple.0 = NO_RIPPLE;
+            }
+        }
+    }
+
+    // Color of key `index` for the current frame
+    fn render(&self, index: usize) -> Rgb {
+        let frame = self.frame as usize;
+        let column = index % COLUMNS;
+        match self.animation {
+            Animation::Off => Rgb(0, 0, 0),
+            Animation::Rainbow => {
+                let hue = column * 256 / COLUMNS;
+                Hsv(hue as u8, 255, 255).rotate((frame * 4) as u8).to_rgb()
+            }
+            Animation::Breathing => scale(ANIMATION_COLOR, triangle(frame * 8)),
+            Animation::Wave => {
+                let position = (frame / 2) % (COLUMNS * 2);
+                let distance = if position > column {
+                    position - column
+                } else {
+                    column - position
+                };
+                let level = 255usize.saturating_sub(distance * 64);
+                scale(ANIMATION_COLOR, level as u8)
+            }
+            Animation::Reactive => scale(ANIMATION_COLOR, self.fade[index]),
+            Animation::RainbowCycle => Hsv(0, 255, 255)
+                .rotate((frame as u8).wrapping_mul(2))
+                .to_rgb(),
+            Animation::Gradient => {
+                let position = (column * 512 / COLUMNS + 512 - frame % 512) % 512;
+                blend(ANIMATION_COLOR, GRADIENT_COLOR, triangle(position))
+            }
+            Animation::Ripple => {
+                let level = self
+                    .ripples
+                    .iter()
+                    .filter(|r| r.0 != NO_RIPPLE)
+                    .map(|&(key, radius)| ripple_level(key as usize, index, radius))
+                    .max()
+                    .unwrap_or(0);
+                scale(ANIMATION_COLOR, level)
+            }
+        }
+    }
+}
+
+/// Runs once at power-up: a column of ANIMATION_COLOR sweeps across the
+/// keys, then the firmware version's major, minor and patch number light up
+/// on the number, top and home rows for SPLASH_VERSION_TIME scans.
+pub struct Splash {
+    // sweep columns come first, then the version
+    frame: usize,
+    next_frame: Option<u32>,
+    running: bool,
+}
+
+impl Splash {
+    pub const fn new() -> Splash {
+        Splash {
+            frame: 0,
+            next_frame: None,
+            running: BOOT_SPLASH,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Renders into `framebuffer` if a frame is due, returns false once the
+    /// splash is over.
+    pub fn tick(&mut self, now: u32, framebuffer: &mut Framebuffer) -> bool {
+        if let Some(next_frame) = self.next_frame {
+            if (now.wrapping_sub(next_frame) as i32) < 0 {
+                return self.running;
+            }
+        }
+
+        if self.frame < COLUMNS {
+            for index in 0..KEYS {
+                let color = if index % COLUMNS == self.frame {
+                    ANIMATION_COLOR
+                } else {
+                    Rgb(0, 0, 0)
+                };
+                framebuffer.set_index_now(index, color, LedMode::On);
+            }
+            self.next_frame = Some(now.wrapping_add(ANIMATION_INTERVAL));
+        } else if self.frame == COLUMNS {
+            for index in 0..KEYS {
+                framebuffer.set_index_now(index, Rgb(0, 0, 0), LedMode::On);
+            }
+            for (row, number) in version().iter().enumerate() {
+                // the digit keys: 1 to 9 and then 0
+                let column = match *number {
+                    0 => 10,
+                    number => number.min(9) as usize,
+                };
+                framebuffer.set_index_now(row * COLUMNS + column, Rgb(255, 255, 255), LedMode::On);
+            }
+            self.next_frame = Some(now.wrapping_add(SPLASH_VERSION_TIME));
+        } else {
+            self.running = false;
+        }
+        self.frame += 1;
+        self.running
+    }
+}
+
+const TEST_COLORS: [Rgb; 4] = [
+    Rgb(0xff, 0x00, 0x00),
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0x00, 0x00, 0xff),
+    Rgb(0xff, 0xff, 0xff),
+];
+
+/// Lights up one key at a time in red, green, blue and white at full
+/// brightness, each for TEST_PATTERN_TIME scans, so dead LEDs stand out.
+pub struct TestPattern {
+    // key and color index, None when the pattern isn't running
+    step: Option<usize>,
+    next_step: Option<u32>,
+}
+
+impl TestPattern {
+    pub const fn new() -> TestPattern {
+        TestPattern {
+            step: None,
+            next_step: None,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.step.is_some()
+    }
+
+    /// Starts over with the first key, the other keys have to be dark.
+    pub fn start(&mut self) {
+        self.step = Some(0);
+        self.next_step = None;
+    }
+
+    /// Renders into `framebuffer` if the next color is due, returns false
+    /// once all keys were shown.
+    pub fn tick(&mut self, now: u32, framebuffer: &mut Framebuffer) -> bool {
+        let mut step = match self.step {
+            Some(step) => step,
+            None => return false,
+        };
+        if let Some(next_step) = self.next_step {
+            if (now.wrapping_sub(next_step) as i32) < 0 {
+                return true;
+            }
+        }
+
+        if step > 0 {
+            framebuffer.set_index_now((step - 1) / 4, Rgb(0, 0, 0), LedMode::On);
+        }
+        // positions without a key are skipped
+        while step < KEYS * 4 && NO_KEYS.iter().any(|key| *key as usize == step / 4) {
+            step += 4;
+        }
+        if step >= KEYS * 4 {
+            self.step = None;
+            return false;
+        }
+
+        framebuffer.set_index_now(step / 4, TEST_COLORS[step % 4], LedMode::On);
+        self.step = Some(step + 1);
+        self.next_step = Some(now.wrapping_add(TEST_PATTERN_TIME));
+        true
+    }
+}
+
+// Major, minor and patch number of the firmware
+fn version() -> [u8; 3] {
+    let number = |n: &str| n.parse().unwrap_or(0);
+    [
+        number(env!("CARGO_PKG_VERSION_MAJOR")),
+        number(env!("CARGO_PKG_VERSION_MINOR")),
+        number(env!("CARGO_PKG_VERSION_PATCH")),
+    ]
+}
+
+// Distance between the centers of two keys in quarter keys, the longer side
+// plus 3/8 of the shorter one is within a few percent of the diagonal
+fn distance(a: usize, b: usize) -> usize {
+    let ((ax, ay), (bx, by)) = (KEY_POSITIONS[a], KEY_POSITIONS[b]);
+    let dx = (isize::from(ax) - isize::from(bx)).abs() as usize;
+    let dy = (isize::from(ay) - isize::from(by)).abs() as usize;
+    dx.max(dy) + dx.min(dy) * 3 / 8
+}
+
+// Brightness of key `index` in the ring `radius` quarter keys around `key`,
+// brightest on the ring and dimmer the further it spread
+fn ripple_level(key: usize, index: usize, radius: u8) -> u8 {
+    let off = (distance(key, index) as isize - radius as isize).abs() as usize;
+    if off >= RIPPLE_WIDTH {
+        return 0;
+    }
+    let ring = 255 - off * 255 / RIPPLE_WIDTH;
+    let spread = 255 - usize::from(radius) * 255 / usize::from(RIPPLE_RADIUS);
+    (ring * spread / 255) as u8
+}
+
+// 0 up to 255 and back down again over 512 steps
+fn triangle(step: usize) -> u8 {
+    let step = step % 512;
+    if step < 256 {
+        step as u8
+    } else {
+        (511 - step) as u8
+    }
+}
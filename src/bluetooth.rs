@@ -1,6 +1,7 @@
 #![feature(const_fn)]
 
 use super::hidreport::HidReport;
+use super::layout::{RECONNECT_ATTEMPTS, RECONNECT_DELAY};
 use super::led::Led;
 use super::protocol::{BleOp, KeyboardOp, LedOp, MacroOp, Message, MsgType, SystemOp};
 use super::serial::{DmaUsart, Serial, Transfer};
@@ -17,10 +18,122 @@ pub enum BluetoothMode {
     Ble,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum Pairing {
+    Off,
+    /// discoverable, a new host gets saved into this slot
+    Discoverable(u8),
+}
+
+/// What the Bluetooth module is doing, as far as its messages tell.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,
+    /// discoverable for any host
+    Advertising,
+    Connected(u8),
+    /// discoverable, a new host gets saved
+    Pairing,
+}
+
+/// The hosts as far as the Bluetooth module told us, shown on the BT layer.
+#[derive(Copy, Clone)]
+pub struct BluetoothStatus {
+    pub mode: BluetoothMode,
+    /// bit n is set if host n is saved
+    pub saved_hosts: u8,
+    pub connected_host: Option<u8>,
+    pub pairing: Pairing,
+    pub connection: ConnectionState,
+    /// the host profile picked last, it's still connecting while it isn't
+    /// the connected host
+    pub profile: Option<u8>,
+}
+
+pub const HOSTS: u8 = 4;
+
+/// A Ble message from the Bluetooth module, decoded.
+#[derive(Copy, Clone, PartialEq)]
+pub enum BluetoothEvent {
+    /// the module is awake, messages can be sent again
+    Wakeup,
+    On,
+    Off,
+    Broadcasting,
+    /// the legacy or BLE mode asked for is active
+    ModeChanged,
+    HostDeleted,
+    /// pairing a new host finished, false if the module reported an error
+    Paired(bool),
+    /// ConnectHost was taken, the host list tells whether the host is there
+    Connecting,
+    /// a host asks to pair
+    PairRequest,
+    Disconnected,
+    /// the battery level in percent
+    Battery(u8),
+    /// the module asks for the battery level
+    BatteryRequest,
+    HostList {
+        /// bit n is set if host n is saved
+        saved: u8,
+        connected: Option<u8>,
+        mode: BluetoothMode,
+    },
+    /// an operation that isn't decoded, or that came without its data
+    Unknown(u8),
+}
+
+impl BluetoothEvent {
+    pub fn decode(operation: u8, data: &[u8]) -> BluetoothEvent {
+        match BleOp::from(operation) {
+            BleOp::AckWakeup => BluetoothEvent::Wakeup,
+            // data = [0]
+            // TODO: always getting a [0] too much?
+            BleOp::AckOn => BluetoothEvent::On,
+            // data = [0]
+            BleOp::AckOff => BluetoothEvent::Off,
+            BleOp::AckBroadcast => BluetoothEvent::Broadcasting,
+            // data = [0]
+            BleOp::AckCompatibilityMode => BluetoothEvent::ModeChanged,
+            // data = [0]
+            BleOp::AckDeleteHost => BluetoothEvent::HostDeleted,
+            // data = [0] if the host was saved
+            BleOp::AckSaveHost => BluetoothEvent::Paired(data.first().map_or(true, |&e| e == 0)),
+            BleOp::AckConnectHost => BluetoothEvent::Connecting,
+            BleOp::Pair => BluetoothEvent::PairRequest,
+            BleOp::Disconnect => BluetoothEvent::Disconnected,
+            BleOp::Battery => BluetoothEvent::BatteryRequest,
+            // data = [battery level in percent]
+            BleOp::AckBattery if !data.is_empty() => BluetoothEvent::Battery(data[0]),
+            // data = [saved hosts, connected host, mode]
+            BleOp::AckHostListQuery if data.len() == 3 => BluetoothEvent::HostList {
+                saved: data[0],
+                connected: if data[1] < HOSTS { Some(data[1]) } else { None },
+                mode: match data[2] {
+                    0 => BluetoothMode::Ble,
+                    1 => BluetoothMode::Legacy,
+                    _ => BluetoothMode::Unknown,
+                },
+            },
+            _ => BluetoothEvent::Unknown(operation),
+        }
+    }
+}
+
 pub struct Bluetooth<BUFFER: 'static + Unsize<[u8]>> {
     pub serial: Serial<BluetoothUsart, BUFFER>,
     pub rx_transfer: Option<Transfer<BUFFER>>,
-    mode: BluetoothMode,
+    status: BluetoothStatus,
+    // the BT layer is active, status changes are shown right away
+    status_shown: bool,
+    // the status is on the LEDs, for the BT layer or while pairing
+    overlay: bool,
+    // the mode asked for, until the module acknowledges it
+    requested_mode: Option<BluetoothMode>,
+    // (failed attempts, scans until the next one) while reconnecting to a
+    // host whose link dropped
+    reconnect: Option<(u8, u32)>,
 }
 
 impl<BUFFER> Bluetooth<BUFFER>
@@ -35,7 +148,18 @@ where
         Bluetooth {
             serial,
             rx_transfer: Some(rx_transfer),
-            mode: BluetoothMode::Unknown,
+            status: BluetoothStatus {
+                mode: BluetoothMode::Unknown,
+                saved_hosts: 0,
+                connected_host: None,
+                pairing: Pairing::Off,
+                connection: ConnectionState::Disconnected,
+                profile: None,
+            },
+            status_shown: false,
+            overlay: false,
+            requested_mode: None,
+            reconnect: None,
         }
     }
 
@@ -44,13 +168,75 @@ where
     }
 
     pub fn off(&mut self) -> nb::Result<(), !> {
-        self.serial.send(MsgType::Ble, BleOp::Off as u8, &[])
+        self.serial.send(MsgType::Ble, BleOp::Off as u8, &[])?;
+        // the module reports a disconnect afterwards, that's no dropped link
+        self.status.connection = ConnectionState::Disconnected;
+        self.reconnect = None;
+        Ok(())
     }
 
+    /// Pairs a new host into slot `host`, until the module acknowledges it.
     pub fn save_host(&mut self, host: u8) -> nb::Result<(), !> {
         // TODO: host < 4?
+        self.reconnect = None;
+        self.status.pairing = Pairing::Discoverable(host);
+        self.status.connection = ConnectionState::Pairing;
         self.serial
-            .send(MsgType::Ble, BleOp::SaveHost as u8, &[host])
+            .send(MsgType::Ble, BleOp::SaveHost as u8, &[host])?;
+        self.host_list_query()
+    }
+
+    /// Switches Bluetooth on and makes the keyboard discoverable, the new
+    /// host is saved as `profile`. The status is shown on the LEDs until the
+    /// module acknowledges it or exit_pairing() is called.
+    pub fn enter_pairing(&mut self, profile: u8) -> nb::Result<(), !> {
+        if profile >= HOSTS {
+            return Ok(());
+        }
+        self.on()?;
+        self.save_host(profile)
+    }
+
+    /// Stops pairing and goes back to the active profile, if there's one.
+    pub fn exit_pairing(&mut self) -> nb::Result<(), !> {
+        if self.status.pairing == Pairing::Off {
+            return Ok(());
+        }
+        self.status.pairing = Pairing::Off;
+        self.status.connection = ConnectionState::Disconnected;
+        match self.status.profile {
+            // the acknowledgement updates the LEDs
+            Some(host) => self.connect_host(host),
+            None => self.host_list_query(),
+        }
+    }
+
+    pub fn pairing(&self) -> Pairing {
+        self.status.pairing
+    }
+
+    /// The connection as far as the module's messages tell, for the LEDs and
+    /// power saving to react to.
+    pub fn connection(&self) -> ConnectionState {
+        self.status.connection
+    }
+
+    /// Switches to the host saved in slot `host`, 0 to HOSTS - 1, and keeps
+    /// it as the active profile. The LEDs catch up once the module
+    /// acknowledges it.
+    pub fn select_profile(&mut self, host: u8) -> nb::Result<(), !> {
+        if host >= HOSTS {
+            return Ok(());
+        }
+        self.status.profile = Some(host);
+        self.reconnect = None;
+        self.connect_host(host)
+    }
+
+    /// The host profile picked last, or the one the module reported as
+    /// connected.
+    pub fn profile(&self) -> Option<u8> {
+        self.status.profile
     }
 
     pub fn connect_host(&mut self, host: u8) -> nb::Result<(), !> {
@@ -59,23 +245,65 @@ where
     }
 
     pub fn delete_host(&mut self, host: u8) -> nb::Result<(), !> {
+        if self.status.profile == Some(host) {
+            self.status.profile = None;
+        }
         self.serial
             .send(MsgType::Ble, BleOp::DeleteHost as u8, &[host])
     }
 
+    /// Forgets the bonds of all hosts, for when one refuses to reconnect.
+    pub fn delete_all_hosts(&mut self) -> nb::Result<(), !> {
+        for host in 0..HOSTS {
+            self.delete_host(host)?;
+        }
+        Ok(())
+    }
+
     pub fn broadcast(&mut self) -> nb::Result<(), !> {
+        self.reconnect = None;
         self.serial.send(MsgType::Ble, BleOp::Broadcast as u8, &[])
     }
 
-    pub fn enable_compatibility_mode(&mut self, enabled: bool) -> nb::Result<(), !> {
-        let on = if enabled { 1 } else { 0 };
+    /// Switches Bluetooth on and broadcasts, so a new host can pair.
+    pub fn pair(&mut self) -> nb::Result<(), !> {
+        self.on()?;
+        self.broadcast()
+    }
+
+    /// Switches the module to `mode`, mode() follows once the module
+    /// acknowledges it.
+    pub fn set_mode(&mut self, mode: BluetoothMode) -> nb::Result<(), !> {
+        let legacy = match mode {
+            BluetoothMode::Ble => 0,
+            BluetoothMode::Legacy => 1,
+            BluetoothMode::Unknown => return Ok(()),
+        };
         self.serial
-            .send(MsgType::Ble, BleOp::CompatibilityMode as u8, &[on])
+            .send(MsgType::Ble, BleOp::CompatibilityMode as u8, &[legacy])?;
+        self.requested_mode = Some(mode);
+        Ok(())
     }
 
+    /// The mode the module last confirmed.
+    pub fn mode(&self) -> BluetoothMode {
+        self.status.mode
+    }
+
+    pub fn enable_compatibility_mode(&mut self, enabled: bool) -> nb::Result<(), !> {
+        let mode = if enabled {
+            BluetoothMode::Legacy
+        } else {
+            BluetoothMode::Ble
+        };
+        self.set_mode(mode)
+    }
+
+    /// Switches between BLE and legacy mode, starting from the mode asked for
+    /// last if the module didn't confirm it yet.
     pub fn toggle_compatibility_mode(&mut self) -> nb::Result<(), !> {
-        let enabled: bool = self.mode == BluetoothMode::Ble;
-        self.enable_compatibility_mode(enabled)
+        let mode = self.requested_mode.unwrap_or(self.status.mode);
+        self.enable_compatibility_mode(mode == BluetoothMode::Ble)
     }
 
     pub fn host_list_query(&mut self) -> nb::Result<(), !> {
@@ -83,6 +311,8 @@ where
             .send(MsgType::Ble, BleOp::HostListQuery as u8, &[])
     }
 
+    /// Sends a keyboard report. The module's HID descriptor only covers
+    /// keycodes up to Application, there's no known report for media keys.
     pub fn send_report(&mut self, report: &HidReport) -> nb::Result<(), !> {
         self.serial.send(
             MsgType::Keyboard,
@@ -91,11 +321,74 @@ where
         )
     }
 
-    pub fn update_led(&self, led: &mut Led<BUFFER>) -> nb::Result<(), !> {
-        led.bluetooth_mode(self.mode)
+    /// Asks for the battery level, the answer is shown on the number row.
+    pub fn query_battery(&mut self) -> nb::Result<(), !> {
+        self.serial.send(MsgType::Ble, BleOp::Battery as u8, &[])
+    }
+
+    /// Runs once per scan: tries to connect to the host whose link dropped
+    /// again, with twice the delay after every failed attempt, and
+    /// broadcasts once they're used up.
+    pub fn tick(&mut self) -> nb::Result<(), !> {
+        let (attempts, scans) = match self.reconnect {
+            Some(reconnect) => reconnect,
+            None => return Ok(()),
+        };
+        if scans > 1 {
+            self.reconnect = Some((attempts, scans - 1));
+            return Ok(());
+        }
+        let host = match self.status.profile {
+            Some(host) if attempts < RECONNECT_ATTEMPTS => host,
+            _ => {
+                debug!("bt reconnect failed").ok();
+                return self.broadcast();
+            }
+        };
+        self.connect_host(host)?;
+        // the acknowledgement asks whether the host is back
+        let delay = RECONNECT_DELAY << (attempts + 1).min(16);
+        self.reconnect = Some((attempts + 1, delay));
+        Ok(())
+    }
+
+    /// Starts or stops showing the status on the LEDs, they're updated
+    /// whenever the module reports a change meanwhile.
+    pub fn show_status(&mut self, shown: bool, led: &mut Led<BUFFER>) -> nb::Result<(), !> {
+        self.status_shown = shown;
+        self.update_led(led)?;
+        if !shown {
+            return Ok(());
+        }
+        // the answer brings the saved and connected hosts up to date
+        self.host_list_query()
+    }
+
+    /// Shows the status while the BT layer is active or the keyboard is
+    /// discoverable, and brings back the keys from before afterwards.
+    pub fn update_led(&mut self, led: &mut Led<BUFFER>) -> nb::Result<(), !> {
+        let shown = self.status_shown || self.status.pairing != Pairing::Off;
+        if !shown {
+            if self.overlay {
+                self.overlay = false;
+                return led.end_overlay();
+            }
+            return Ok(());
+        }
+        if !self.overlay {
+            self.overlay = true;
+            led.begin_overlay();
+        }
+        led.bluetooth_mode(&self.status)
     }
 
-    pub fn handle_message(&mut self, message: &Message, led: &mut Led<BUFFER>) {
+    /// Handles a message from the Bluetooth module, messages of type Ble are
+    /// returned decoded.
+    pub fn handle_message(
+        &mut self,
+        message: &Message,
+        led: &mut Led<BUFFER>,
+    ) -> Option<BluetoothEvent> {
         match message.msg_type {
             MsgType::System => {
                 match SystemOp::from(message.operation) {
@@ -146,56 +439,12 @@ where
                 }
             }
             MsgType::Ble => {
-                match BleOp::from(message.operation) {
-                    BleOp::AckWakeup => {
-                        // nothing to do here, this message only only lets us know
-                        // that we can now safely send
-                    }
-                    BleOp::AckOn => {
-                        // data = [0]
-                        // TODO: always getting a [0] too much?
-                        //debug!("bt ack on: {:?}", message.data).ok();
-                    }
-                    BleOp::AckOff => {
-                        // data = [0]
-                        //debug!("bt ack off: {:?}", message.data).ok();
-                    }
-                    BleOp::AckCompatibilityMode => {
-                        // data = [0]
-                        //debug!("bt ack compatibility mode: {:?}", message.data).ok();
-                    }
-                    BleOp::AckDeleteHost => {
-                        // data = [0]
-                        //debug!("bt ack delete host: {:?}", message.data).ok();
-                    }
-                    BleOp::Pair => {
-                        debug!("bt pair").ok();
-                        /*
-                        self.serial.send(MsgType::System,
-                                         SystemOp::IsSyncCode as u8,
-                                         &[1]);
-                                         */
-                    }
-                    BleOp::Disconnect => {
-                        // check this? sent after off, 14
-                        debug!("bt disconnect").ok();
-                    }
-                    BleOp::AckHostListQuery => {
-                        if message.data.len() == 3 {
-                            self.mode = match message.data[2] {
-                                0 => BluetoothMode::Ble,
-                                1 => BluetoothMode::Legacy,
-                                _ => BluetoothMode::Unknown,
-                            }
-                        }
-
-                        self.update_led(led).log_error();
-                        debug!("bt host list: {:?}", message.data).ok();
-                    }
-                    _ => {
-                        debug!("msg: Ble {} {:?}", message.operation, message.data).ok();
-                    }
+                let event = BluetoothEvent::decode(message.operation, message.data);
+                if let BluetoothEvent::Unknown(_) = event {
+                    debug!("msg: Ble {} {:?}", message.operation, message.data).ok();
                 }
+                self.handle_event(event, led);
+                return Some(event);
             }
             MsgType::Led => match LedOp::from(message.operation) {
                 LedOp::ThemeMode => {
@@ -236,6 +485,89 @@ where
                 ).ok();
             }
         }
+        None
+    }
+
+    fn handle_event(&mut self, event: BluetoothEvent, led: &mut Led<BUFFER>) {
+        match event {
+            BluetoothEvent::Off => {
+                self.status.connection = ConnectionState::Disconnected;
+            }
+            BluetoothEvent::Broadcasting => {
+                if self.status.connection != ConnectionState::Pairing {
+                    self.status.connection = ConnectionState::Advertising;
+                }
+            }
+            BluetoothEvent::ModeChanged => {
+                if let Some(mode) = self.requested_mode.take() {
+                    self.status.mode = mode;
+                    self.update_led(led).log_error();
+                }
+            }
+            BluetoothEvent::HostDeleted => {
+                self.host_list_query().log_error();
+            }
+            BluetoothEvent::Paired(_) => {
+                self.status.pairing = Pairing::Off;
+                // the host list tells whether the new host connected
+                self.status.connection = ConnectionState::Disconnected;
+                self.host_list_query().log_error();
+            }
+            BluetoothEvent::Connecting => {
+                self.host_list_query().log_error();
+            }
+            BluetoothEvent::PairRequest => {
+                debug!("bt pair").ok();
+                /*
+                self.serial.send(MsgType::System,
+                                 SystemOp::IsSyncCode as u8,
+                                 &[1]);
+                                 */
+            }
+            BluetoothEvent::Disconnected => {
+                // check this? sent after off, 14
+                debug!("bt disconnect").ok();
+                let dropped = match self.status.connection {
+                    ConnectionState::Connected(_) => RECONNECT_ATTEMPTS > 0,
+                    _ => false,
+                };
+                if dropped {
+                    self.reconnect = Some((0, RECONNECT_DELAY));
+                }
+                self.status.connected_host = None;
+                self.status.connection = ConnectionState::Disconnected;
+                self.update_led(led).log_error();
+            }
+            BluetoothEvent::Battery(percent) => {
+                led.show_battery(percent).log_error();
+            }
+            BluetoothEvent::HostList {
+                saved,
+                connected,
+                mode,
+            } => {
+                self.status.saved_hosts = saved;
+                self.status.connected_host = connected;
+                if connected.is_some() {
+                    self.status.profile = connected;
+                    self.reconnect = None;
+                }
+                self.status.connection = match (self.status.connection, connected) {
+                    (ConnectionState::Pairing, _) => ConnectionState::Pairing,
+                    (_, Some(host)) => ConnectionState::Connected(host),
+                    (ConnectionState::Connected(_), None) => ConnectionState::Disconnected,
+                    (connection, None) => connection,
+                };
+                self.status.mode = mode;
+                self.update_led(led).log_error();
+            }
+            // Wakeup only lets us know that we can now safely send, it's
+            // handled once the receive buffer is free again
+            BluetoothEvent::Wakeup
+            | BluetoothEvent::On
+            | BluetoothEvent::BatteryRequest
+            | BluetoothEvent::Unknown(_) => {}
+        }
     }
 
     pub fn poll(&mut self, led: &mut Led<BUFFER>) {
@@ -254,15 +586,12 @@ where
                         operation: buffer[2],
                         data: &buffer[3..3 + buffer[1] as usize - 1],
                     };
-                    self.handle_message(&message, led);
-
-                    match (message.msg_type, message.operation) {
-                        (MsgType::Ble, 170) => {
-                            // Wakeup acknowledged, send data
-                            self.serial.usart.ack_wakeup();
-                            self.serial.send_buffer_pos = 0;
-                        }
-                        _ => {}
+                    let event = self.handle_message(&message, led);
+
+                    if event == Some(BluetoothEvent::Wakeup) {
+                        // Wakeup acknowledged, send data
+                        self.serial.usart.ack_wakeup();
+                        self.serial.send_buffer_pos = 0;
                     }
                 }
 
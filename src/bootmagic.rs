@@ -0,0 +1,25 @@
+use keycodes::KeyIndex;
+use keymatrix::KeyMatrix;
+use settings::Settings;
+use stm32l151::SCB;
+
+// SYSRESETREQ, together with the key that's needed to write AIRCR
+const SYSTEM_RESET: u32 = 0x05FA_0004;
+
+/// Checks the keys held down at power-up, before the keyboard starts:
+///
+/// - Esc restarts into the bootloader, which stays in DFU mode while Esc is
+///   held
+/// - Space + B resets all settings to their defaults
+pub fn check(matrix: &KeyMatrix, scb: &SCB, settings: &mut Settings) {
+    let held = |key: KeyIndex| matrix.state[key as usize];
+
+    if held(KeyIndex::Escape) {
+        unsafe { scb.aircr.write(SYSTEM_RESET) };
+        loop {}
+    }
+
+    if held(KeyIndex::Space) && held(KeyIndex::B) {
+        settings.reset();
+    }
+}
@@ -0,0 +1,68 @@
+use framebuffer::Rgb;
+
+/// A color as hue, saturation and value, each from 0 to 255. The hue goes
+/// around the color wheel: 0 is red, 85 green and 170 blue.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Hsv(pub u8, pub u8, pub u8);
+
+impl Hsv {
+    #[allow(dead_code)]
+    pub fn from_rgb(color: Rgb) -> Hsv {
+        let (r, g, b) = (i32::from(color.0), i32::from(color.1), i32::from(color.2));
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0 {
+            return Hsv(0, 0, max as u8);
+        }
+
+        // negative hues wrap around to the end of the color wheel
+        let hue = if max == r {
+            43 * (g - b) / delta
+        } else if max == g {
+            85 + 43 * (b - r) / delta
+        } else {
+            171 + 43 * (r - g) / delta
+        };
+        Hsv(hue as u8, (delta * 255 / max) as u8, max as u8)
+    }
+
+    pub fn to_rgb(self) -> Rgb {
+        let Hsv(hue, saturation, value) = self;
+        let (s, v) = (u16::from(saturation), u16::from(value));
+        let sector = hue / 43;
+        let rising = u16::from(hue - sector * 43) * 6;
+
+        let low = (v * (255 - s) / 255) as u8;
+        let falling = (v * (255 - s * rising / 255) / 255) as u8;
+        let rising = (v * (255 - s * (255 - rising) / 255) / 255) as u8;
+        match sector {
+            0 => Rgb(value, rising, low),
+            1 => Rgb(falling, value, low),
+            2 => Rgb(low, value, rising),
+            3 => Rgb(low, falling, value),
+            4 => Rgb(rising, low, value),
+            _ => Rgb(value, low, falling),
+        }
+    }
+
+    /// Moves `steps` further around the color wheel.
+    pub fn rotate(self, steps: u8) -> Hsv {
+        Hsv(self.0.wrapping_add(steps), self.1, self.2)
+    }
+}
+
+/// `color` moved `steps` around the color wheel, at the same saturation and
+/// brightness.
+#[allow(dead_code)]
+pub fn rotate_hue(color: Rgb, steps: u8) -> Rgb {
+    Hsv::from_rgb(color).rotate(steps).to_rgb()
+}
+
+/// Mixes `amount` (0 - 255) of `b` into `a`.
+pub fn blend(a: Rgb, b: Rgb, amount: u8) -> Rgb {
+    let mix = |a: u8, b: u8| {
+        let (a, b, amount) = (u16::from(a), u16::from(b), u16::from(amount));
+        ((a * (255 - amount) + b * amount) / 255) as u8
+    };
+    Rgb(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
@@ -0,0 +1,85 @@
+use action::Action;
+use keycodes::KeyIndex;
+use keymatrix::{KeyMatrix, KeyState};
+use layout::{COMBOS, COMBO_COUNT, COMBO_TERM};
+
+/// Two keys pressed together trigger `action` instead of their own actions.
+pub struct Combo {
+    pub keys: [KeyIndex; 2],
+    pub action: Action,
+}
+
+pub struct Combos {
+    // presses of combo keys that haven't been passed on yet
+    held_back: KeyState,
+    active: [bool; COMBO_COUNT],
+}
+
+impl Combos {
+    pub const fn new() -> Combos {
+        Combos {
+            held_back: [false; 70],
+            active: [false; COMBO_COUNT],
+        }
+    }
+
+    /// Checks whether combo `id` got pressed or released and returns the
+    /// (action, pressed, changed) event for this scan.
+    ///
+    /// Keys that form a combo are added to `masked` until they're released.
+    pub fn poll(
+        &mut self,
+        id: usize,
+        matrix: &KeyMatrix,
+        masked: &mut KeyState,
+    ) -> Option<(Action, bool, bool)> {
+        let combo = &COMBOS[id];
+        let a = combo.keys[0] as usize;
+        let b = combo.keys[1] as usize;
+        let both_pressed = matrix.state[a] && matrix.state[b];
+
+        if self.active[id] {
+            if both_pressed {
+                Some((combo.action, true, false))
+            } else {
+                self.active[id] = false;
+                Some((combo.action, false, true))
+            }
+        } else if both_pressed && self.held_back[a] && self.held_back[b] {
+            self.active[id] = true;
+            self.held_back[a] = false;
+            self.held_back[b] = false;
+            masked[a] = true;
+            masked[b] = true;
+            Some((combo.action, true, true))
+        } else {
+            None
+        }
+    }
+
+    /// Holds back new presses of combo keys for COMBO_TERM so a combo
+    /// can still form. Keys released before that are passed on as a tap.
+    pub fn filter(
+        &mut self,
+        matrix: &KeyMatrix,
+        previous: &KeyState,
+        masked: &KeyState,
+        state: &mut KeyState,
+    ) {
+        for combo in COMBOS.iter() {
+            for index in combo.keys.iter() {
+                let key = *index as usize;
+                if masked[key] {
+                    continue;
+                }
+                if matrix.state[key] && !previous[key] && matrix.held_for(key) < COMBO_TERM {
+                    state[key] = false;
+                    self.held_back[key] = true;
+                } else if self.held_back[key] {
+                    state[key] = true;
+                    self.held_back[key] = false;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use action::Action;
+use keycodes::KeyCode;
+use layout::{COMPOSE_SEQUENCES, COMPOSE_TIMEOUT};
+
+/// Two keys typed after the Compose key and the character they stand for.
+pub struct ComposeSequence {
+    pub keys: (KeyCode, KeyCode),
+    pub character: char,
+}
+
+pub struct Compose {
+    active: bool,
+    first: Option<KeyCode>,
+    since: u32,
+    composed: Option<char>,
+}
+
+impl Compose {
+    pub const fn new() -> Compose {
+        Compose {
+            active: false,
+            first: None,
+            since: 0,
+            composed: None,
+        }
+    }
+
+    /// Takes the next two key presses after the Compose key.
+    ///
+    /// Returns true if the press was captured and must not be sent.
+    pub fn capture(&mut self, action: &Action, now: u32) -> bool {
+        match *action {
+            Action::Compose => {
+                self.active = true;
+                self.first = None;
+                self.since = now;
+                true
+            }
+            Action::Key(code) if self.active && !code.is_modifier() => {
+                match self.first {
+                    None => self.first = Some(code),
+                    Some(first) => {
+                        self.active = false;
+                        self.composed = COMPOSE_SEQUENCES
+                            .iter()
+                            .find(|s| s.keys == (first, code))
+                            .map(|s| s.character);
+                    }
+                }
+                self.since = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the composed character once both keys are typed.
+    pub fn poll(&mut self, now: u32) -> Option<char> {
+        if self.active && now.wrapping_sub(self.since) >= COMPOSE_TIMEOUT {
+            self.active = false;
+        }
+        self.composed.take()
+    }
+}
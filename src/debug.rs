@@ -1,6 +1,10 @@
 // TODO: install exception handler to deal with hio semihosting not being available
 // and just ignore bkpts if no debugger attached
 use core::fmt;
+use core::sync::atomic::AtomicBool;
+
+/// Drops all debug output while set, see Action::PrivacyMode.
+pub static QUIET: AtomicBool = AtomicBool::new(false);
 
 #[cfg(feature = "use_semihosting")]
 #[macro_export]
@@ -8,11 +12,16 @@ macro_rules! debug {
     ($($arg: tt)*) => {
         {
             use core::fmt::Write;
+            use core::sync::atomic::Ordering;
             use cortex_m_semihosting::hio;
 
-            match hio::hstdout() {
-                Ok(ref mut stdout) => write!(stdout, $($arg)*),
-                _ => Ok(())
+            if $crate::debug::QUIET.load(Ordering::Relaxed) {
+                Ok(())
+            } else {
+                match hio::hstdout() {
+                    Ok(ref mut stdout) => write!(stdout, $($arg)*),
+                    _ => Ok(())
+                }
             }
         }
     }
@@ -0,0 +1,86 @@
+use hidreport::HidReport;
+
+const BUFFER_SIZE: usize = 64;
+
+/// Records the reports sent while recording is on and plays them back later.
+///
+/// The reports are kept in a ring buffer, so only the last BUFFER_SIZE
+/// changes of a long recording are kept.
+pub struct DynamicMacro {
+    reports: [HidReport; BUFFER_SIZE],
+    start: usize,
+    len: usize,
+    recording: bool,
+    playing: Option<usize>,
+}
+
+impl DynamicMacro {
+    pub const fn new() -> DynamicMacro {
+        DynamicMacro {
+            reports: [HidReport::new(); BUFFER_SIZE],
+            start: 0,
+            len: 0,
+            recording: false,
+            playing: None,
+        }
+    }
+
+    pub fn toggle_recording(&mut self) {
+        self.playing = None;
+        self.recording = !self.recording;
+        if self.recording {
+            self.start = 0;
+            self.len = 0;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    pub fn play(&mut self) {
+        self.recording = false;
+        self.playing = Some(0);
+    }
+
+    pub fn record(&mut self, report: &HidReport) {
+        if !self.recording {
+            return;
+        }
+
+        if self.len < BUFFER_SIZE {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % BUFFER_SIZE;
+        }
+        let end = (self.start + self.len - 1) % BUFFER_SIZE;
+        self.reports[end] = *report;
+    }
+
+    /// Returns the recorded report to send in this scan while playing.
+    ///
+    /// Moves on to the next report only once the previous one got sent.
+    pub fn poll(&mut self, report_sent: bool) -> Option<HidReport> {
+        let next = match self.playing {
+            Some(next) => next,
+            None => return None,
+        };
+
+        let next = if !report_sent && next > 0 {
+            next - 1
+        } else {
+            next
+        };
+        if next < self.len {
+            self.playing = Some(next + 1);
+            Some(self.reports[(self.start + next) % BUFFER_SIZE])
+        } else {
+            self.playing = None;
+            None
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use color::blend;
+use gamma::Gamma;
+use keycodes::KeyIndex;
+use keymatrix::{COLUMNS, ROWS};
+use layout::{GAMMA, TRANSITION_FRAMES};
+use led::LedMode;
+
+pub const KEYS: usize = ROWS * COLUMNS;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+const SET_KEYS_MARKER: u8 = 0xca;
+// more keys don't fit into the LED send buffer
+const KEYS_PER_MESSAGE: usize = 24;
+
+/// A SetIndividualKeys payload: the 0xca marker, the number of keys and then
+/// [index, r, g, b, mode] for each key.
+pub struct KeyColorFrame {
+    payload: [u8; 2 + KEYS_PER_MESSAGE * 5],
+}
+
+impl KeyColorFrame {
+    pub fn new() -> KeyColorFrame {
+        let mut payload = [0; 2 + KEYS_PER_MESSAGE * 5];
+        payload[0] = SET_KEYS_MARKER;
+        KeyColorFrame { payload }
+    }
+
+    /// Adds key `index`, returns false if the frame is full or there's no
+    /// such key.
+    pub fn push(&mut self, index: usize, color: Rgb, mode: LedMode) -> bool {
+        let count = self.len();
+        if count == KEYS_PER_MESSAGE || index >= KEYS {
+            return false;
+        }
+        let Rgb(r, g, b) = color;
+        let entry = 2 + count * 5;
+        self.payload[entry..entry + 5].copy_from_slice(&[index as u8, r, g, b, mode as u8]);
+        self.payload[1] += 1;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.payload[1] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.payload[..2 + self.len() * 5]
+    }
+}
+
+/// The set keys of a Framebuffer, see Framebuffer::snapshot().
+pub struct Snapshot {
+    keys: [(Rgb, LedMode); KEYS],
+    lit: [bool; KEYS],
+}
+
+/// Color and mode of every key, in KeyIndex order.
+///
+/// Changes are kept in RAM until Led::flush sends the changed keys to the LED
+/// controller, which shows its theme on keys that were never set.
+///
+/// Set colors and the brightness fade in over `transition` frames, tick()
+/// moves them on by a frame.
+pub struct Framebuffer {
+    keys: [(Rgb, LedMode); KEYS],
+    // the color each key fades from and how many frames of it have passed
+    fading: [(Rgb, u8); KEYS],
+    // keys set since the theme came back
+    lit: [bool; KEYS],
+    // set keys that still have to be sent
+    dirty: [bool; KEYS],
+    gamma: Gamma,
+    transition: u8,
+    // (target, start, frames passed) of the brightness of all set keys
+    brightness: (u8, u8, u8),
+    // applied on top of the brightness of all keys
+    key_brightness: [u8; KEYS],
+}
+
+impl Framebuffer {
+    pub const fn new() -> Framebuffer {
+        Framebuffer {
+            keys: [(Rgb(0, 0, 0), LedMode::On); KEYS],
+            fading: [(Rgb(0, 0, 0), 0); KEYS],
+            lit: [false; KEYS],
+            dirty: [false; KEYS],
+            gamma: GAMMA,
+            transition: TRANSITION_FRAMES,
+            brightness: (0xff, 0xff, TRANSITION_FRAMES),
+            key_brightness: [0xff; KEYS],
+        }
+    }
+
+    /// Sets how many frames color and brightness changes take, 0 makes them
+    /// jump.
+    pub fn set_transition(&mut self, frames: u8) {
+        self.transition = frames;
+    }
+
+    /// Fades all set keys to `level` (0 - 255) of their color.
+    pub fn set_brightness(&mut self, level: u8) {
+        let current = self.current_brightness();
+        self.brightness = (level, current, 0);
+    }
+
+    /// Scales key `key` to `level` (0 - 255) of its brightness, on top of
+    /// set_brightness(). Dimming the others keeps indicator keys bright.
+    pub fn set_key_brightness(&mut self, key: KeyIndex, level: u8) {
+        let index = key as usize;
+        self.key_brightness[index] = level;
+        if self.lit[index] {
+            self.dirty[index] = true;
+        }
+    }
+
+    /// Forgets all keys, for when the LED controller went back to its theme.
+    pub fn clear(&mut self) {
+        for index in 0..KEYS {
+            self.lit[index] = false;
+            self.dirty[index] = false;
+        }
+    }
+
+    /// Stops setting key `index`, it shows the theme again once the theme
+    /// comes back.
+    pub fn forget(&mut self, index: usize) {
+        if index < KEYS {
+            self.lit[index] = false;
+            self.dirty[index] = false;
+        }
+    }
+
+    /// A copy of the colors of all set keys, to bring them back with
+    /// restore() once an overlay is gone.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            keys: self.keys,
+            lit: self.lit,
+        }
+    }
+
+    /// Sets the keys that were set in `snapshot` again, the others are left
+    /// alone.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        for index in 0..KEYS {
+            if snapshot.lit[index] {
+                let (color, mode) = snapshot.keys[index];
+                self.set_index(index, color, mode);
+            }
+        }
+    }
+
+    /// Moves all fades on by a frame, returns whether anything changed.
+    pub fn tick(&mut self) -> bool {
+        let transition = self.transition;
+        let brightness_changed = self.brightness.2 < transition;
+        if brightness_changed {
+            self.brightness.2 += 1;
+        }
+
+        let mut changed = brightness_changed;
+        for index in 0..KEYS {
+            if !self.lit[index] {
+                continue;
+            }
+            let fading = self.fading[index].1 < transition;
+            if fading {
+                self.fading[index].1 += 1;
+            }
+            if fading || brightness_changed {
+                self.dirty[index] = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Picks the curve applied to colors sent from now on.
+    pub fn set_gamma(&mut self, gamma: Gamma) {
+        self.gamma = gamma;
+    }
+
+    pub fn gamma(&self) -> Gamma {
+        self.gamma
+    }
+
+    pub fn set(&mut self, key: KeyIndex, color: Rgb, mode: LedMode) {
+        self.set_index(key as usize, color, mode);
+    }
+
+    /// Fades key `index` from its current color to `color`, keys that
+    /// showed the theme start from black.
+    pub fn set_index(&mut self, index: usize, color: Rgb, mode: LedMode) {
+        if index < KEYS {
+            let from = if self.lit[index] {
+                self.color(index)
+            } else {
+                Rgb(0, 0, 0)
+            };
+            self.fading[index] = (from, 0);
+            self.keys[index] = (color, mode);
+            self.lit[index] = true;
+            self.dirty[index] = true;
+        }
+    }
+
+    /// Sets key `index` to `color` right away, for animations that already
+    /// change colors smoothly.
+    pub fn set_index_now(&mut self, index: usize, color: Rgb, mode: LedMode) {
+        self.set_index(index, color, mode);
+        if index < KEYS {
+            self.fading[index].1 = self.transition;
+        }
+    }
+
+    pub fn fill(&mut self, color: Rgb, mode: LedMode) {
+        for index in 0..KEYS {
+            self.set_index(index, color, mode);
+        }
+    }
+
+    /// Adds changed keys to `frame` until it's full, with the gamma curve
+    /// applied, and returns how many were added. They count as changed until
+    /// marked sent.
+    pub fn encode(&self, frame: &mut KeyColorFrame) -> usize {
+        let mut count = 0;
+        for index in (0..KEYS).filter(|index| self.dirty[*index]) {
+            let mode = self.keys[index].1;
+            let color = scale(self.color(index), self.current_brightness());
+            let color = scale(color, self.key_brightness[index]);
+            if !frame.push(index, self.gamma.apply(color), mode) {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Marks all set keys as changed, to send them again.
+    pub fn redraw(&mut self) {
+        for index in 0..KEYS {
+            self.dirty[index] = self.lit[index];
+        }
+    }
+
+    /// Marks the first `count` changed keys as sent.
+    pub fn mark_sent(&mut self, count: usize) {
+        for dirty in self.dirty.iter_mut().filter(|dirty| **dirty).take(count) {
+            *dirty = false;
+        }
+    }
+
+    // The color key `index` is at in its fade
+    fn color(&self, index: usize) -> Rgb {
+        let (from, frame) = self.fading[index];
+        let amount = tween(0, 255, frame, self.transition);
+        blend(from, self.keys[index].0, amount)
+    }
+
+    fn current_brightness(&self) -> u8 {
+        let (to, from, frame) = self.brightness;
+        tween(from, to, frame, self.transition)
+    }
+}
+
+// `frame` frames of `frames` along the way from `from` to `to`
+fn tween(from: u8, to: u8, frame: u8, frames: u8) -> u8 {
+    if frame >= frames {
+        return to;
+    }
+    let (from, to) = (i32::from(from), i32::from(to));
+    (from + (to - from) * i32::from(frame) / i32::from(frames)) as u8
+}
+
+/// `color` at `level` (0 - 255) of its brightness.
+pub fn scale(color: Rgb, level: u8) -> Rgb {
+    let scale = |c: u8| (u16::from(c) * u16::from(level) / 255) as u8;
+    Rgb(scale(color.0), scale(color.1), scale(color.2))
+}
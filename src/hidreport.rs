@@ -1,5 +1,6 @@
 use core::slice;
 
+#[derive(Clone, Copy)]
 #[repr(packed)]
 pub struct HidReport {
     pub modifiers: u8,
@@ -8,7 +9,7 @@ pub struct HidReport {
 }
 
 impl HidReport {
-    pub fn new() -> HidReport {
+    pub const fn new() -> HidReport {
         HidReport {
             modifiers: 0,
             _unused: 0,
@@ -0,0 +1,35 @@
+use hidreport::HidReport;
+use keycodes::KeyCode;
+use layout::KEY_OVERRIDES;
+
+/// Sends `replacement` instead of `key` while one of `modifiers` is held.
+/// The modifiers are left out of the report in that case.
+pub struct KeyOverride {
+    pub modifiers: &'static [KeyCode],
+    pub key: KeyCode,
+    pub replacement: KeyCode,
+}
+
+/// Rewrites the report according to layout::KEY_OVERRIDES.
+pub fn apply(report: &mut HidReport) {
+    for key_override in KEY_OVERRIDES.iter() {
+        let mask = key_override
+            .modifiers
+            .iter()
+            .fold(0, |mask, modifier| mask | modifier.modifier_bit());
+        if report.modifiers & mask == 0 {
+            continue;
+        }
+
+        let mut overridden = false;
+        for key in report.keys.iter_mut() {
+            if *key == key_override.key as u8 {
+                *key = key_override.replacement as u8;
+                overridden = true;
+            }
+        }
+        if overridden {
+            report.modifiers &= !mask;
+        }
+    }
+}
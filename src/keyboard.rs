@@ -1,17 +1,76 @@
 use action::Action;
-use bluetooth::Bluetooth;
+use animation::Animation;
+use bluetooth::{Bluetooth, ConnectionState};
+use combo::Combos;
+use compose::Compose;
 use core::marker::Unsize;
-use debug::UnwrapLog;
+use core::sync::atomic::Ordering;
+use debug::{UnwrapLog, QUIET};
+use dynamic_macro::DynamicMacro;
+use framebuffer::{scale, Rgb};
 use hidreport::HidReport;
+use key_override;
 use keycodes::KeyCode;
-use keymatrix::KeyState;
+use keymap::Keymap;
+use keymatrix::{KeyMatrix, KeyState, COLUMNS};
+use layout::HEATMAP_PERSIST;
 use layout::LAYERS;
 use layout::LAYER_BT;
-use led::Led;
+use layout::LAYER_COLORS;
+use layout::LAYER_THEMES;
+use layout::{
+    ANIMATION_INTERVAL, AUTO_SHIFT_TERM, COMBO_COUNT, MACRO_PLAY_COLOR, MACRO_PULSE_PERIOD,
+    MACRO_RECORD_COLOR, MODIFIER_COLOR, MODIFIER_LEDS, NIGHT_BRIGHTNESS, NIGHT_HOURS,
+    ONESHOT_COLOR, ONESHOT_TIMEOUT, RETRO_TAPPING, SWAP_HANDS, TAPPING_TERM, TAPPING_TERMS,
+    TAP_DANCES, TAP_DANCE_COUNT, TAP_HOLD_POLICY, TRI_LAYER,
+};
+use leader::Leader;
+use led::{Led, LedMode};
+use macros::{MacroPlayer, VERSION};
+use rtc::{Rtc, Time};
+use settings::Settings;
+use stats::TypingStats;
+use steno::Steno;
+use tapdance::TapDances;
+use unicode::UnicodeInput;
 
 pub struct Keyboard {
+    keymap: Keymap,
+    steno: Steno,
     layers: Layers,
+    tap_dances: TapDances,
+    one_shot: OneShot,
+    one_shot_indicator: OneShotIndicator,
+    modifier_indicator: ModifierIndicator,
+    caps_word: CapsWord,
+    auto_shift: AutoShift,
+    leader: Leader,
+    compose: Compose,
+    key_lock: KeyLock,
+    combos: Combos,
+    macros: MacroPlayer,
+    dynamic_macro: DynamicMacro,
+    macro_indicator: MacroIndicator,
+    unicode: UnicodeInput,
+    gaming: GamingMode,
+    privacy: PrivacyMode,
+    layer_theme: LayerTheme,
+    layer_indicator: LayerIndicator,
+    clock: ClockDisplay,
+    night_mode: NightMode,
+    stats: TypingStats,
+    swap_hands: SwapHands,
+    host_settings: HostSettings,
+    tap_hold: TapHoldBuffer,
+    // keys whose current press was taken by a subsystem and must be ignored
+    masked: KeyState,
+    // when the latest key press happened, and when the latest released key
+    // got pressed
+    last_press: u32,
+    last_tap: u32,
     previous_state: KeyState, // TODO: use packed state here
+    previous_report: HidReport,
+    report_sent: bool,
 }
 
 fn eq(sa: &KeyState, sb: &KeyState) -> bool {
@@ -21,17 +80,64 @@ fn eq(sa: &KeyState, sb: &KeyState) -> bool {
 impl Keyboard {
     pub const fn new() -> Keyboard {
         Keyboard {
+            keymap: Keymap::new(),
+            steno: Steno::new(),
             layers: Layers::new(),
+            tap_dances: TapDances::new(),
+            one_shot: OneShot::new(),
+            one_shot_indicator: OneShotIndicator::new(),
+            modifier_indicator: ModifierIndicator::new(),
+            caps_word: CapsWord::new(),
+            auto_shift: AutoShift::new(),
+            leader: Leader::new(),
+            compose: Compose::new(),
+            key_lock: KeyLock::new(),
+            combos: Combos::new(),
+            macros: MacroPlayer::new(),
+            dynamic_macro: DynamicMacro::new(),
+            macro_indicator: MacroIndicator::new(),
+            unicode: UnicodeInput::new(),
+            gaming: GamingMode::new(),
+            privacy: PrivacyMode::new(),
+            layer_theme: LayerTheme::new(),
+            layer_indicator: LayerIndicator::new(),
+            clock: ClockDisplay::new(),
+            night_mode: NightMode::new(),
+            stats: TypingStats::new(),
+            swap_hands: SwapHands::new(),
+            host_settings: HostSettings::new(),
+            tap_hold: TapHoldBuffer::new(),
+            masked: [false; 70],
+            last_press: 0,
+            last_tap: 0,
             previous_state: [false; 70],
+            previous_report: HidReport::new(),
+            report_sent: true,
         }
     }
 
+    /// Loads the active keymap and switches to the default layer saved in the
+    /// settings.
+    pub fn load_settings(&mut self, settings: &Settings) {
+        self.keymap.load(settings);
+        if HEATMAP_PERSIST {
+            self.stats.load(settings);
+        }
+        let layer = settings.default_layer();
+        if (layer as usize) < LAYERS.len() {
+            self.layers.set_default(layer);
+            self.layers.current = self.layers.next;
+        }
+    }
+
+    /// Resolves `key` on the active layers, from the highest to the lowest.
+    /// Transparent entries fall through to the next active layer below.
     fn get_action(&self, key: usize) -> Action {
         let mut action = Action::Transparent;
 
         for i in (0..LAYERS.len()).rev() {
             if self.layers.current & (1 << i) != 0 {
-                action = LAYERS[i][key];
+                action = self.keymap.get(i as u8, key);
             }
             if action != Action::Transparent {
                 break;
@@ -43,46 +149,502 @@ impl Keyboard {
 
     pub fn process<BUFFER>(
         &mut self,
-        state: &KeyState,
+        matrix: &KeyMatrix,
         bluetooth: &mut Bluetooth<BUFFER>,
         led: &mut Led<BUFFER>,
+        rtc: &Rtc,
+        settings: &mut Settings,
     ) where
         BUFFER: Unsize<[u8]>,
     {
-        // TODO: might not even need this check after switching to wakeup only handling?
-        if !eq(&self.previous_state, state) {
-            let mut hid = HidProcessor::new();
+        // Dual-role keys depend on how long they're held, so this has to run
+        // on every scan, not only when the matrix state changes.
+        let mut state = matrix.state;
+        let now = matrix.ticks;
+        let mut hid = HidProcessor::new();
+        self.one_shot.tick(now);
+        self.caps_word.now = now;
+
+        for id in 0..TAP_DANCE_COUNT {
+            let event = self.tap_dances.poll(id, now);
+            if let Some((action, pressed, changed)) = event {
+                self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed);
+            }
+        }
+
+        let event = self.macros.poll(now, self.report_sent);
+        if let Some((action, pressed, changed)) = event {
+            self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed);
+        }
+
+        if let Some(c) = self.compose.poll(now) {
+            self.unicode.type_code_point(c);
+        }
+
+        let event = self.unicode.poll(self.report_sent);
+        if let Some((action, pressed, changed)) = event {
+            self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed);
+        }
 
-            for (key, pressed) in state.iter().enumerate() {
-                let changed = self.previous_state[key] != *pressed;
+        let event = self.leader.poll(now);
+        if let Some((action, pressed, changed)) = event {
+            self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed);
+        }
 
-                // Only handle currently pressed and changed keys to
-                // cut down on processing time.
-                if *pressed || changed {
-                    let action = self.get_action(key);
-                    hid.process(&action, *pressed, changed);
-                    led.process(&action, *pressed, changed);
-                    bluetooth.process(&action, *pressed, changed);
-                    self.layers.process(&action, *pressed, changed);
+        for id in 0..COMBO_COUNT {
+            let event = self.combos.poll(id, matrix, &mut self.masked);
+            if let Some((action, pressed, changed)) = event {
+                self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed);
+            }
+        }
+        if !self.gaming.on {
+            self.combos
+                .filter(matrix, &self.previous_state, &self.masked, &mut state);
+        }
+        let gaming = self.gaming.on;
+        let privacy = self.privacy.on;
+        let wpm_shown = self.stats.wpm_shown;
+        let heatmap_shown = self.stats.heatmap_shown;
+        let default_layer = self.layers.default;
+        let keymap = self.keymap.active;
+
+        // presses held back for an undecided mod-tap key still count for its
+        // decision
+        let matrix_state = self.tap_hold.state;
+        for (key, pressed) in state.iter().enumerate() {
+            if matrix_state[key] != *pressed {
+                let pressed_at = matrix.pressed_at[key];
+                if *pressed {
+                    self.last_press = pressed_at;
+                    led.key_pressed(key, pressed_at);
+                    self.stats.key_pressed(key);
+                } else if is_later(pressed_at, self.last_tap) {
+                    self.last_tap = pressed_at;
                 }
             }
+        }
 
-            let bt_layer_current: bool = self.layers.current & (1 << LAYER_BT) != 0;
-            let bt_layer_next: bool = self.layers.next & (1 << LAYER_BT) != 0;
-            if bt_layer_next && !bt_layer_current {
-                bluetooth.update_led(led).log_error();
-            } else if bt_layer_current && !bt_layer_next {
-                led.theme_mode().log_error();
+        self.tap_hold.filter(&self.previous_state, &mut state);
+
+        let mut undecided = None;
+        for (key, pressed) in state.iter().enumerate() {
+            let changed = self.previous_state[key] != *pressed;
+
+            if self.masked[key] {
+                self.masked[key] = *pressed;
+                continue;
+            }
+
+            // Only handle currently pressed and changed keys to
+            // cut down on processing time.
+            if *pressed || changed {
+                let physically_pressed = *pressed;
+                let position = self.swap_hands.position(key, physically_pressed, changed);
+                let action = self.gaming.filter(self.get_action(position));
+                // a layer-tap key's layer is on right away, the tap is resolved
+                // with the other dual-role keys
+                if let (Action::LayerTap(layer, _), true) = (action, changed) {
+                    let layer = Action::LayerMomentary(layer);
+                    self.dispatch(&mut hid, bluetooth, led, &layer, *pressed, changed);
+                }
+                let held_for = matrix.held_for(key);
+                let term = tapping_term(key);
+                let hold = Hold {
+                    duration: held_for,
+                    interrupted: is_later(self.last_press, matrix.pressed_at[key]),
+                    other_tapped: is_later(self.last_tap, matrix.pressed_at[key]),
+                    term,
+                    retro_tapping: RETRO_TAPPING.iter().any(|index| *index as usize == key),
+                    forced: self.tap_hold.held == Some(key),
+                };
+                if let (Action::ModTap(..), true) = (action, physically_pressed) {
+                    if !hold.is_hold() {
+                        undecided = Some(key);
+                    }
+                }
+                let (action, pressed) = resolve_tap_hold(action, *pressed, &hold);
+                let (action, pressed) = if gaming {
+                    (action, pressed)
+                } else {
+                    self.auto_shift.resolve(action, pressed, held_for)
+                };
+                let captured = changed
+                    && pressed
+                    && (self.leader.capture(&action, now)
+                        || self.compose.capture(&action, now)
+                        || self.key_lock.capture(&action));
+                if captured {
+                    self.masked[key] = physically_pressed;
+                    continue;
+                }
+                if changed && pressed {
+                    self.macro_indicator.key_pressed(key, &action);
+                    self.one_shot_indicator.key_pressed(key, &action);
+                }
+                match action {
+                    Action::TapDance(id) => {
+                        self.tap_dances.process(id, pressed, changed, now, term)
+                    }
+                    _ => self.dispatch(&mut hid, bluetooth, led, &action, pressed, changed),
+                }
+            }
+        }
+        self.tap_hold.undecided = undecided;
+
+        if self.gaming.on != gaming {
+            led.gaming_mode(self.gaming.on).log_error();
+        }
+        if let Some(wpm) = self.stats.poll(now) {
+            if self.stats.wpm_shown {
+                led.show_wpm(wpm).log_error();
+            }
+            if self.stats.heatmap_shown {
+                led.show_heatmap(&self.stats).log_error();
+            }
+        }
+        if heatmap_shown != self.stats.heatmap_shown && HEATMAP_PERSIST {
+            self.stats.save(settings);
+        }
+        let wpm_hidden = wpm_shown && !self.stats.wpm_shown;
+        if wpm_hidden || (heatmap_shown && !self.stats.heatmap_shown) {
+            led.theme_mode().log_error();
+        }
+        let time = rtc.time();
+        self.clock.update(time, led);
+        self.night_mode.update(time, led);
+        if self.privacy.on != privacy {
+            let on = self.privacy.on;
+            led.privacy_mode(on).log_error();
+            QUIET.store(on, Ordering::Relaxed);
+        }
+
+        if self.layers.default != default_layer {
+            let layer = self.layers.default;
+            settings.set_default_layer(layer);
+        }
+        let connected = match bluetooth.connection() {
+            ConnectionState::Connected(host) => Some(host),
+            _ => None,
+        };
+        self.host_settings
+            .update(connected, &mut self.keymap, &mut self.unicode, settings);
+        if self.keymap.active != keymap {
+            let id = self.keymap.active;
+            settings.set_active_keymap(id);
+        }
+
+        let layers = self.layers.current;
+        self.layers.finish();
+        self.layers_changed(layers, bluetooth, led);
+        // switching the shown layer colors resets the theme
+        let redraw = self.layers.current != layers;
+        let latched = (self.one_shot.pending, self.layers.sticky);
+        self.one_shot_indicator.update(latched, redraw, led);
+        self.key_lock.report(&mut hid);
+        for code in self.macros.held() {
+            hid.add_key(*code);
+        }
+        for code in self.steno.keys(self.report_sent) {
+            hid.add_key(*code);
+        }
+        hid.report.modifiers |= self.one_shot.modifiers();
+        let held = hid.report.modifiers | self.one_shot.pending;
+        self.modifier_indicator.update(held, redraw, led);
+        hid.report.modifiers |= self.caps_word.modifiers(&hid.report);
+        hid.report.modifiers |= self.unicode.modifiers();
+        hid.finish();
+        key_override::apply(&mut hid.report);
+
+        let playback = self.dynamic_macro.poll(self.report_sent);
+        if let Some(report) = playback {
+            hid.report = report;
+        }
+        self.macro_indicator.update(&self.dynamic_macro, now, led);
+
+        self.report_sent = true;
+        if hid.report.as_bytes() != self.previous_report.as_bytes() {
+            if bluetooth.send_report(&hid.report).is_ok() {
+                self.dynamic_macro.record(&hid.report);
+                self.previous_report = hid.report;
+            } else {
+                self.report_sent = false;
+            }
+        }
+
+        bluetooth.tick().log_error();
+
+        if !eq(&self.previous_state, &state) {
+            led.send_keys(&state);
+            self.previous_state = state;
+        }
+        led.animate(now);
+        // framebuffer changes that didn't fit into the send buffer before
+        led.flush().log_error();
+    }
+
+    /// Runs the layer hooks for every layer that got switched on or off since
+    /// `previous`.
+    fn layers_changed<BUFFER>(
+        &mut self,
+        previous: u16,
+        bluetooth: &mut Bluetooth<BUFFER>,
+        led: &mut Led<BUFFER>,
+    ) where
+        BUFFER: Unsize<[u8]>,
+    {
+        let changed = previous ^ self.layers.current;
+        for layer in 0..LAYERS.len() as u8 {
+            if changed & (1 << layer) != 0 {
+                let active = self.layers.current & (1 << layer) != 0;
+                bluetooth.layer_changed(layer, active, led);
+                // switching the theme resets the colors of all keys
+                self.layer_theme.layer_changed(layer, active, led);
+                self.layer_indicator
+                    .layer_changed(layer, active, &self.keymap, led);
             }
+        }
+    }
+
+    fn dispatch<BUFFER>(
+        &mut self,
+        hid: &mut HidProcessor,
+        bluetooth: &mut Bluetooth<BUFFER>,
+        led: &mut Led<BUFFER>,
+        action: &Action,
+        pressed: bool,
+        changed: bool,
+    ) where
+        BUFFER: Unsize<[u8]>,
+    {
+        hid.process(action, pressed, changed);
+        led.process(action, pressed, changed);
+        bluetooth.process(action, pressed, changed);
+        self.layers.process(action, pressed, changed);
+        self.keymap.process(action, pressed, changed);
+        self.steno.process(action, pressed, changed);
+        self.one_shot.process(action, pressed, changed);
+        self.caps_word.process(action, pressed, changed);
+        self.auto_shift.process(action, pressed, changed);
+        self.macros.process(action, pressed, changed);
+        self.dynamic_macro.process(action, pressed, changed);
+        self.unicode.process(action, pressed, changed);
+        self.gaming.process(action, pressed, changed);
+        self.privacy.process(action, pressed, changed);
+        self.stats.process(action, pressed, changed);
+        self.clock.process(action, pressed, changed);
+        self.swap_hands.process(action, pressed, changed);
+    }
+}
 
-            self.layers.finish();
+/// Tapping term of `key`, from TAPPING_TERMS or the default TAPPING_TERM.
+fn tapping_term(key: usize) -> u32 {
+    TAPPING_TERMS
+        .iter()
+        .find(|&&(index, _)| index as usize == key)
+        .map_or(TAPPING_TERM, |&(_, term)| term)
+}
 
-            bluetooth.send_report(&hid.report).log_error();
-            led.send_keys(state).log_error();
+// Whether tick a comes after tick b
+fn is_later(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
 
-            self.previous_state = *state;
+/// How to decide between tap and hold for dual-role keys.
+#[allow(dead_code)]
+pub enum TapHoldPolicy {
+    /// hold once the tapping term is over
+    Timeout,
+    /// also hold once another key got pressed and released meanwhile
+    PermissiveHold,
+    /// also hold as soon as another key gets pressed
+    HoldOnOtherKeyPress,
+}
+
+/// How a key has been held so far.
+struct Hold {
+    /// scans since the key got pressed
+    duration: u32,
+    term: u32,
+    /// another key was pressed after this one
+    interrupted: bool,
+    /// another key was pressed after this one and is released again
+    other_tapped: bool,
+    /// tap even after the tapping term if no other key was pressed
+    retro_tapping: bool,
+    /// counts as held right away, see TapHoldBuffer
+    forced: bool,
+}
+
+impl Hold {
+    fn is_hold(&self) -> bool {
+        let decided_early = match TAP_HOLD_POLICY {
+            TapHoldPolicy::Timeout => false,
+            TapHoldPolicy::PermissiveHold => self.other_tapped,
+            TapHoldPolicy::HoldOnOtherKeyPress => self.interrupted,
+        };
+        decided_early || self.forced || self.duration >= self.term
+    }
+
+    /// A key that counts as held, released without pressing another key.
+    fn is_retro_tap(&self, pressed: bool) -> bool {
+        self.retro_tapping && !pressed && !self.interrupted
+    }
+}
+
+const TAP_HOLD_BUFFER: usize = 16;
+
+/// Holds back the other keys' presses and releases while a mod-tap key is
+/// undecided, and replays them one per scan once it's decided. They get its
+/// modifier if it counts as held, and come after its key if it's tapped.
+///
+/// Once the buffer is full the mod-tap key counts as held, further changes
+/// wait in the matrix until the replay made room for them.
+struct TapHoldBuffer {
+    /// the undecided mod-tap key as of the latest scan
+    undecided: Option<usize>,
+    /// a mod-tap key that counts as held because the buffer filled up
+    held: Option<usize>,
+    /// the matrix state of the latest scan
+    state: KeyState,
+    // the state the buffered events lead to
+    queued: KeyState,
+    // (key, pressed), the oldest first
+    events: [(u8, bool); TAP_HOLD_BUFFER],
+    len: usize,
+}
+
+impl TapHoldBuffer {
+    const fn new() -> TapHoldBuffer {
+        TapHoldBuffer {
+            undecided: None,
+            held: None,
+            state: [false; 70],
+            queued: [false; 70],
+            events: [(0, false); TAP_HOLD_BUFFER],
+            len: 0,
         }
     }
+
+    /// Turns the matrix `state` into the one to process, `previous` is the
+    /// one processed by the latest scan.
+    fn filter(&mut self, previous: &KeyState, state: &mut KeyState) {
+        if let Some(key) = self.held {
+            if !previous[key] {
+                self.held = None;
+            }
+        }
+        self.state = *state;
+        for key in 0..state.len() {
+            if state[key] == self.queued[key] {
+                continue;
+            }
+            let held_back = match self.undecided {
+                Some(undecided) => key != undecided,
+                None => self.len > 0,
+            };
+            if held_back && self.len == TAP_HOLD_BUFFER {
+                if let Some(undecided) = self.undecided.take() {
+                    self.held = Some(undecided);
+                }
+                continue;
+            }
+            if held_back {
+                self.events[self.len] = (key as u8, state[key]);
+                self.len += 1;
+            }
+            self.queued[key] = state[key];
+        }
+        if self.len == 0 {
+            return;
+        }
+
+        for key in 0..state.len() {
+            if self.state[key] != self.queued[key] {
+                state[key] = previous[key];
+            }
+        }
+        for &(key, _) in &self.events[..self.len] {
+            state[key as usize] = previous[key as usize];
+        }
+        if self.undecided.is_none() {
+            let (key, pressed) = self.events[0];
+            state[key as usize] = pressed;
+            for i in 1..self.len {
+                self.events[i - 1] = self.events[i];
+            }
+            self.len -= 1;
+        }
+    }
+}
+
+/// Turns dual-role actions into the action they stand for right now.
+///
+/// A mod-tap key does nothing while it's undecided, becomes its modifier once
+/// it counts as held (see TAP_HOLD_POLICY) and sends its key for a single scan
+/// if released before that.
+///
+/// A space cadet key is its modifier right away and sends its key shifted if
+/// it's released before the tapping term without another key being pressed.
+/// Layer-tap keys work the same, their layer is switched on by the caller.
+///
+/// With retro tapping, both also send their tap key when they're released
+/// after the tapping term without another key being pressed.
+///
+/// Bluetooth profile and pairing keys decide on release: held for the tapping
+/// term they connect to their host or start pairing, otherwise they tap their
+/// key.
+///
+/// A turbo key alternates between pressed and released every `rate` scans for
+/// as long as it's held.
+fn resolve_tap_hold(action: Action, pressed: bool, hold: &Hold) -> (Action, bool) {
+    match action {
+        Action::ModTap(_, key) if hold.is_retro_tap(pressed) => (Action::Key(key), true),
+        Action::SpaceCadet(_, key) if hold.is_retro_tap(pressed) => (Action::Shifted(key), true),
+        Action::LayerTap(_, key) if hold.is_retro_tap(pressed) => (Action::Key(key), true),
+        Action::ModTap(modifier, key) => {
+            if hold.is_hold() {
+                (Action::Key(modifier), pressed)
+            } else if pressed {
+                (Action::Nop, true)
+            } else {
+                (Action::Key(key), true)
+            }
+        }
+        Action::SpaceCadet(modifier, key) => {
+            if pressed || hold.duration >= hold.term || hold.interrupted {
+                (Action::Key(modifier), pressed)
+            } else {
+                (Action::Shifted(key), true)
+            }
+        }
+        Action::LayerTap(_, key) => {
+            if pressed || hold.duration >= hold.term || hold.interrupted {
+                (Action::Nop, pressed)
+            } else {
+                (Action::Key(key), true)
+            }
+        }
+        Action::BtProfile(host, key) => {
+            decide_on_release(Action::BtConnectHost(host), key, pressed, hold)
+        }
+        Action::BtPair(key) => decide_on_release(action, key, pressed, hold),
+        Action::Turbo(key, rate) => {
+            let rate = u32::from(rate).max(1);
+            (Action::Key(key), pressed && (hold.duration / rate) % 2 == 0)
+        }
+        _ => (action, pressed),
+    }
+}
+
+// Nothing while pressed, `held` or a tap of `key` on release
+fn decide_on_release(held: Action, key: KeyCode, pressed: bool, hold: &Hold) -> (Action, bool) {
+    if pressed {
+        (Action::Nop, true)
+    } else if hold.duration >= hold.term {
+        (held, true)
+    } else {
+        (Action::Key(key), true)
+    }
 }
 
 trait EventProcessor {
@@ -90,9 +652,30 @@ trait EventProcessor {
     fn finish(&mut self) {}
 }
 
+/// Reacts to layers being switched on or off, see Keyboard::layers_changed.
+/// Called once per changed layer after all key events of a scan, so layouts
+/// don't need to know who cares about their layers.
+trait LayerHook<BUFFER>
+where
+    BUFFER: Unsize<[u8]>,
+{
+    fn layer_changed(&mut self, layer: u8, active: bool, led: &mut Led<BUFFER>);
+}
+
 struct Layers {
-    current: u8,
-    next: u8,
+    current: u16,
+    next: u16,
+    // layer on top of the base layer that's always on
+    default: u8,
+    // momentary layers that are held down, and the ones locked on
+    momentary: u16,
+    locked: u16,
+    // one-shot layers that are held down, and whether a key was used meanwhile
+    sticky_held: u16,
+    sticky_used: bool,
+    // one-shot layers waiting for the next key press to finish
+    sticky: u16,
+    sticky_consumed: bool,
 }
 
 impl Layers {
@@ -100,6 +683,73 @@ impl Layers {
         Layers {
             current: 0b1,
             next: 0b1,
+            default: 0,
+            momentary: 0,
+            locked: 0,
+            sticky_held: 0,
+            sticky_used: false,
+            sticky: 0,
+            sticky_consumed: false,
+        }
+    }
+
+    fn set_default(&mut self, layer: u8) {
+        // the base layer stays on underneath
+        self.next &= !(1 << self.default) | 0b1;
+        self.next |= 1 << layer;
+        self.default = layer;
+    }
+
+    fn process_sticky(&mut self, layer: u8, pressed: bool) {
+        if pressed {
+            self.next |= 1 << layer;
+            self.sticky_held |= 1 << layer;
+            self.sticky_used = false;
+        } else {
+            self.sticky_held &= !(1 << layer);
+            if self.sticky_used {
+                self.next &= !(1 << layer);
+            } else {
+                self.sticky |= 1 << layer;
+            }
+        }
+    }
+
+    fn process_momentary(&mut self, layer: u8, pressed: bool) {
+        if pressed {
+            self.next |= 1 << layer;
+            self.momentary |= 1 << layer;
+            // pressing the layer key again unlocks it
+            self.locked &= !(1 << layer);
+        } else {
+            self.momentary &= !(1 << layer);
+            if self.locked & (1 << layer) == 0 {
+                self.next &= !(1 << layer);
+            }
+        }
+    }
+
+    fn process_lock(&mut self) {
+        if self.locked != 0 {
+            self.next &= !(self.locked & !self.momentary);
+            self.locked = 0;
+        } else {
+            self.locked = self.momentary;
+        }
+    }
+
+    fn process_other(&mut self, pressed: bool) {
+        if pressed {
+            if self.sticky_held != 0 {
+                self.sticky_used = true;
+            }
+            if self.sticky != 0 {
+                self.sticky_consumed = true;
+            }
+        } else if self.sticky_consumed {
+            self.next &= !self.sticky;
+            self.sticky = 0;
+            self.sticky_consumed = false;
         }
     }
 }
@@ -108,24 +758,553 @@ impl EventProcessor for Layers {
     fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
         if changed {
             match (*action, pressed) {
-                (Action::LayerMomentary(layer), true) => self.next |= 1 << layer,
-                (Action::LayerMomentary(layer), false) => self.next &= !(1 << layer),
+                (Action::LayerMomentary(layer), _) => self.process_momentary(layer, pressed),
                 (Action::LayerToggle(layer), true) => self.next ^= 1 << layer,
                 (Action::LayerOn(layer), true) => self.next |= 1 << layer,
                 (Action::LayerOff(layer), true) => self.next &= !(1 << layer),
-                _ => {}
+                (Action::LayerOneShot(layer), _) => self.process_sticky(layer, pressed),
+                (Action::LayerLock, true) => self.process_lock(),
+                (Action::DefaultLayer(layer), true) => self.set_default(layer),
+                (Action::LayerLock, false)
+                | (Action::DefaultLayer(_), false)
+                | (Action::LayerToggle(_), false)
+                | (Action::LayerOn(_), false)
+                | (Action::LayerOff(_), false)
+                | (Action::Nop, _)
+                | (Action::Transparent, _) => {}
+                _ => self.process_other(pressed),
             }
         }
     }
 
     fn finish(&mut self) {
+        let (first, second, third) = TRI_LAYER;
+        let both = (1 << first) | (1 << second);
+        if self.next & both == both {
+            self.next |= 1 << third;
+        } else {
+            self.next &= !(1 << third);
+        }
         self.current = self.next;
     }
 }
 
+/// One-shot modifiers: tapping one applies it to the next key press,
+/// holding it works like a regular modifier.
+struct OneShot {
+    held: u8,
+    pending: u8,
+    // a key was pressed while a one-shot modifier was held down
+    used: bool,
+    // a key was pressed while modifiers were pending, apply them now
+    triggered: bool,
+    since: u32,
+    now: u32,
+}
+
+impl OneShot {
+    const fn new() -> OneShot {
+        OneShot {
+            held: 0,
+            pending: 0,
+            used: false,
+            triggered: false,
+            since: 0,
+            now: 0,
+        }
+    }
+
+    fn tick(&mut self, now: u32) {
+        self.now = now;
+        if self.pending != 0 && now.wrapping_sub(self.since) >= ONESHOT_TIMEOUT {
+            self.pending = 0;
+        }
+    }
+
+    /// Modifiers to add to the current report, consumes triggered ones.
+    fn modifiers(&mut self) -> u8 {
+        let mut modifiers = self.held;
+        if self.triggered {
+            modifiers |= self.pending;
+            self.pending = 0;
+            self.triggered = false;
+        }
+        modifiers
+    }
+}
+
+impl EventProcessor for OneShot {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed {
+            match (*action, pressed) {
+                (Action::OneShot(code), true) => {
+                    self.held |= code.modifier_bit();
+                    self.used = false;
+                }
+                (Action::OneShot(code), false) => {
+                    self.held &= !code.modifier_bit();
+                    if !self.used {
+                        self.pending |= code.modifier_bit();
+                        self.since = self.now;
+                    }
+                }
+                (Action::Key(code), true) | (Action::Shifted(code), true)
+                    if code.is_normal_key() =>
+                {
+                    if self.held != 0 {
+                        self.used = true;
+                    }
+                    if self.pending != 0 {
+                        self.triggered = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// one-shot keys whose latched state can be shown at the same time
+const ONESHOT_KEYS: usize = 4;
+
+/// Lights up one-shot modifier and one-shot layer keys in ONESHOT_COLOR while
+/// they're latched, until a key press uses them or they time out.
+struct OneShotIndicator {
+    keys: [Option<(usize, Action)>; ONESHOT_KEYS],
+    // bits of the `keys` that are lit
+    shown: u8,
+}
+
+impl OneShotIndicator {
+    const fn new() -> OneShotIndicator {
+        OneShotIndicator {
+            keys: [None; ONESHOT_KEYS],
+            shown: 0,
+        }
+    }
+
+    fn key_pressed(&mut self, key: usize, action: &Action) {
+        match *action {
+            Action::OneShot(_) | Action::LayerOneShot(_) => {}
+            _ => return,
+        }
+        // the same key again, else a free slot or one that isn't lit
+        let same = self.keys.iter().position(|entry| match *entry {
+            Some((k, _)) => k == key,
+            None => false,
+        });
+        let shown = self.shown;
+        let free = (0..ONESHOT_KEYS).find(|i| shown & (1 << i) == 0);
+        if let Some(slot) = same.or(free) {
+            self.keys[slot] = Some((key, *action));
+        }
+    }
+
+    /// Shows the keys of the latched modifier bits and layers.
+    fn update<BUFFER>(&mut self, latched: (u8, u16), redraw: bool, led: &mut Led<BUFFER>)
+    where
+        BUFFER: Unsize<[u8]>,
+    {
+        let (modifiers, layers) = latched;
+        let mut lit = 0;
+        for (i, entry) in self.keys.iter().enumerate() {
+            let on = match *entry {
+                Some((_, Action::OneShot(code))) => modifiers & code.modifier_bit() != 0,
+                Some((_, Action::LayerOneShot(layer))) => layers & (1 << layer) != 0,
+                _ => false,
+            };
+            if on {
+                lit |= 1 << i;
+            }
+        }
+        if lit == self.shown && !redraw {
+            return;
+        }
+        // the theme mode brings back the theme colors of used keys
+        if self.shown & !lit != 0 {
+            led.theme_mode().log_error();
+        }
+        self.shown = lit;
+
+        for (i, entry) in self.keys.iter().enumerate() {
+            if let (Some((key, _)), true) = (*entry, lit & (1 << i) != 0) {
+                led.framebuffer().set_index(key, ONESHOT_COLOR, LedMode::On);
+            }
+        }
+        led.flush().log_error();
+    }
+}
+
+/// Lights up MODIFIER_LEDS while their modifier is held, locked or latched.
+struct ModifierIndicator {
+    // modifier bits that are lit
+    shown: u8,
+}
+
+impl ModifierIndicator {
+    const fn new() -> ModifierIndicator {
+        ModifierIndicator { shown: 0 }
+    }
+
+    fn update<BUFFER>(&mut self, modifiers: u8, redraw: bool, led: &mut Led<BUFFER>)
+    where
+        BUFFER: Unsize<[u8]>,
+    {
+        let lit = MODIFIER_LEDS
+            .iter()
+            .fold(0, |lit, &(code, _)| lit | modifiers & code.modifier_bit());
+        if lit == self.shown && !redraw {
+            return;
+        }
+        let released = self.shown & !lit;
+        self.shown = lit;
+
+        for &(code, key) in MODIFIER_LEDS {
+            if lit & code.modifier_bit() != 0 {
+                led.framebuffer().set(key, MODIFIER_COLOR, LedMode::On);
+            } else if released & code.modifier_bit() != 0 {
+                led.framebuffer().forget(key as usize);
+            }
+        }
+        if released != 0 {
+            led.refresh_theme().log_error();
+        } else {
+            led.flush().log_error();
+        }
+    }
+}
+
+/// Caps Word: shifts letters until a key that doesn't belong to a word is
+/// pressed. Toggled by double tapping Shift or the CapsWord action.
+struct CapsWord {
+    active: bool,
+    shift_pressed_at: u32,
+    shift_tapped_at: Option<u32>,
+    now: u32,
+}
+
+impl CapsWord {
+    const fn new() -> CapsWord {
+        CapsWord {
+            active: false,
+            shift_pressed_at: 0,
+            shift_tapped_at: None,
+            now: 0,
+        }
+    }
+
+    fn is_shifted(code: KeyCode) -> bool {
+        (code >= KeyCode::A && code <= KeyCode::Z) || code == KeyCode::Minus
+    }
+
+    fn continues_word(code: KeyCode) -> bool {
+        CapsWord::is_shifted(code)
+            || (code >= KeyCode::N1 && code <= KeyCode::N0)
+            || code == KeyCode::BSpace
+            || code.is_modifier()
+    }
+
+    /// Shift to add to the current report.
+    fn modifiers(&self, report: &HidReport) -> u8 {
+        let letters = report.keys.iter().any(|k| {
+            *k >= KeyCode::A as u8 && *k <= KeyCode::Z as u8 || *k == KeyCode::Minus as u8
+        });
+        if self.active && letters {
+            KeyCode::LShift.modifier_bit()
+        } else {
+            0
+        }
+    }
+}
+
+impl EventProcessor for CapsWord {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if !changed {
+            return;
+        }
+
+        match (*action, pressed) {
+            (Action::CapsWord, true) => self.active = !self.active,
+            (Action::Key(KeyCode::LShift), true) | (Action::Key(KeyCode::RShift), true) => {
+                let double_tap = match self.shift_tapped_at {
+                    Some(at) => self.now.wrapping_sub(at) < TAPPING_TERM,
+                    None => false,
+                };
+                if double_tap {
+                    self.active = !self.active;
+                    self.shift_tapped_at = None;
+                }
+                self.shift_pressed_at = self.now;
+            }
+            (Action::Key(KeyCode::LShift), false) | (Action::Key(KeyCode::RShift), false) => {
+                if self.now.wrapping_sub(self.shift_pressed_at) < TAPPING_TERM {
+                    self.shift_tapped_at = Some(self.now);
+                }
+            }
+            (Action::Key(code), true) | (Action::Shifted(code), true) => {
+                if !CapsWord::continues_word(code) {
+                    self.active = false;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Auto Shift: with it switched on, letters, digits and punctuation held for
+/// AUTO_SHIFT_TERM are sent shifted, quick taps unshifted.
+struct AutoShift {
+    enabled: bool,
+}
+
+impl AutoShift {
+    const fn new() -> AutoShift {
+        AutoShift { enabled: false }
+    }
+
+    fn applies_to(code: KeyCode) -> bool {
+        (code >= KeyCode::A && code <= KeyCode::N0)
+            || (code >= KeyCode::Minus && code <= KeyCode::Slash)
+    }
+
+    /// Like resolve_tap_hold: nothing is sent until the key is either
+    /// released or held long enough.
+    fn resolve(&self, action: Action, pressed: bool, held_for: u32) -> (Action, bool) {
+        match action {
+            Action::Key(code) if self.enabled && AutoShift::applies_to(code) => {
+                if held_for >= AUTO_SHIFT_TERM {
+                    (Action::Shifted(code), pressed)
+                } else if pressed {
+                    (Action::Nop, true)
+                } else {
+                    (Action::Key(code), true)
+                }
+            }
+            _ => (action, pressed),
+        }
+    }
+}
+
+impl EventProcessor for AutoShift {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if let (Action::AutoShiftToggle, true, true) = (*action, pressed, changed) {
+            self.enabled = !self.enabled;
+        }
+    }
+}
+
+/// Gaming mode: no Meta keys and no dual-role keys, so every key does one
+/// thing without delay. Combos and Auto Shift are off too.
+struct GamingMode {
+    on: bool,
+}
+
+impl GamingMode {
+    const fn new() -> GamingMode {
+        GamingMode { on: false }
+    }
+
+    fn filter(&self, action: Action) -> Action {
+        if !self.on {
+            return action;
+        }
+
+        match action {
+            Action::Key(KeyCode::LMeta) | Action::Key(KeyCode::RMeta) => Action::Nop,
+            Action::ModTap(_, key) => Action::Key(key),
+            Action::SpaceCadet(modifier, _) => Action::Key(modifier),
+            Action::OneShot(modifier) => Action::Key(modifier),
+            Action::LayerOneShot(layer) => Action::LayerMomentary(layer),
+            Action::LayerTap(layer, _) => Action::LayerMomentary(layer),
+            Action::BtProfile(_, key) | Action::BtPair(key) => Action::Key(key),
+            Action::TapDance(id) => TAP_DANCES
+                .get(id as usize)
+                .map_or(Action::Nop, |dance| dance.actions[0]),
+            _ => action,
+        }
+    }
+}
+
+impl EventProcessor for GamingMode {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if let (Action::GamingMode, true, true) = (*action, pressed, changed) {
+            self.on = !self.on;
+        }
+    }
+}
+
+/// Privacy mode, for screen sharing: blanks the LEDs, so they don't show
+/// what's typed, and silences debug output.
+struct PrivacyMode {
+    on: bool,
+}
+
+impl PrivacyMode {
+    const fn new() -> PrivacyMode {
+        PrivacyMode { on: false }
+    }
+}
+
+impl EventProcessor for PrivacyMode {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if let (Action::PrivacyMode, true, true) = (*action, pressed, changed) {
+            self.on = !self.on;
+        }
+    }
+}
+
+const MAX_LOCKED_KEYS: usize = 4;
+
+/// Key lock: tapping KeyLock and then a key keeps that key held down until
+/// it's pressed again.
+struct KeyLock {
+    armed: bool,
+    // KeyCode::No marks a free slot
+    locked: [KeyCode; MAX_LOCKED_KEYS],
+}
+
+impl KeyLock {
+    const fn new() -> KeyLock {
+        KeyLock {
+            armed: false,
+            locked: [KeyCode::No; MAX_LOCKED_KEYS],
+        }
+    }
+
+    /// Takes presses of KeyLock, of the key to lock and of locked keys.
+    ///
+    /// Returns true if the press was captured and must not be sent.
+    fn capture(&mut self, action: &Action) -> bool {
+        let code = match *action {
+            Action::KeyLock => {
+                self.armed = !self.armed;
+                return true;
+            }
+            Action::Key(code) if code != KeyCode::No => code,
+            _ => return false,
+        };
+
+        if let Some(slot) = self.locked.iter_mut().find(|locked| **locked == code) {
+            *slot = KeyCode::No;
+            return true;
+        }
+        if !self.armed {
+            return false;
+        }
+
+        self.armed = false;
+        let free = self
+            .locked
+            .iter_mut()
+            .find(|locked| **locked == KeyCode::No);
+        if let Some(slot) = free {
+            *slot = code;
+        }
+        true
+    }
+
+    /// Adds the locked keys to the current report.
+    fn report(&self, hid: &mut HidProcessor) {
+        for code in self.locked.iter().filter(|code| **code != KeyCode::No) {
+            hid.add_key(*code);
+        }
+    }
+}
+
+/// The keymap and Unicode mode used with each Bluetooth host: changes are
+/// saved for the connected host and come back when it connects again.
+struct HostSettings {
+    // the host they were applied for last
+    host: Option<u8>,
+}
+
+impl HostSettings {
+    const fn new() -> HostSettings {
+        HostSettings { host: None }
+    }
+
+    fn update(
+        &mut self,
+        connected: Option<u8>,
+        keymap: &mut Keymap,
+        unicode: &mut UnicodeInput,
+        settings: &mut Settings,
+    ) {
+        if connected != self.host {
+            self.host = connected;
+            if let Some((id, mode)) = connected.and_then(|host| settings.host_settings(host)) {
+                keymap.select(id);
+                unicode.mode = mode;
+            }
+        } else if let Some(host) = connected {
+            // unchanged settings aren't written again
+            settings.set_host_settings(host, keymap.active, unicode.mode);
+        }
+    }
+}
+
+/// Swap hands: while held, keys act like their mirror image from SWAP_HANDS.
+/// The one-shot variant also mirrors the next key press after a tap.
+struct SwapHands {
+    held: bool,
+    // a key was pressed while held
+    used: bool,
+    // tapped one-shot, waiting for the next key press
+    pending: bool,
+    // keys that were pressed while swapping, they stay mirrored until released
+    swapped: KeyState,
+}
+
+impl SwapHands {
+    const fn new() -> SwapHands {
+        SwapHands {
+            held: false,
+            used: false,
+            pending: false,
+            swapped: [false; 70],
+        }
+    }
+
+    /// The position in the layout that `key` takes its action from.
+    fn position(&mut self, key: usize, pressed: bool, changed: bool) -> usize {
+        if changed && pressed {
+            self.swapped[key] = self.held || self.pending;
+            self.used = self.held;
+            self.pending = false;
+        }
+
+        if self.swapped[key] {
+            let (row, column) = (key / COLUMNS, key % COLUMNS);
+            row * COLUMNS + SWAP_HANDS[row][column] as usize
+        } else {
+            key
+        }
+    }
+}
+
+impl EventProcessor for SwapHands {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed {
+            match (*action, pressed) {
+                (Action::SwapHands, _) => self.held = pressed,
+                (Action::SwapHandsOneShot, true) => {
+                    self.held = true;
+                    self.used = false;
+                }
+                (Action::SwapHandsOneShot, false) => {
+                    self.held = false;
+                    self.pending = !self.used;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
 struct HidProcessor {
     pub report: HidReport,
     i: usize,
+    grave_escape: bool,
 }
 
 impl HidProcessor {
@@ -133,6 +1312,19 @@ impl HidProcessor {
         HidProcessor {
             report: HidReport::new(),
             i: 0,
+            grave_escape: false,
+        }
+    }
+
+    fn add_key(&mut self, code: KeyCode) {
+        if code.is_modifier() {
+            self.report.modifiers |= code.modifier_bit();
+        } else if code.is_normal_key()
+            && self.i < self.report.keys.len()
+            && !self.report.keys[..self.i].contains(&(code as u8))
+        {
+            self.report.keys[self.i] = code as u8;
+            self.i += 1;
         }
     }
 }
@@ -141,14 +1333,91 @@ impl EventProcessor for HidProcessor {
     fn process(&mut self, action: &Action, pressed: bool, _changed: bool) {
         if pressed {
             match *action {
-                Action::Key(code) => {
-                    if code.is_modifier() {
-                        self.report.modifiers |= 1 << (code as u8 - KeyCode::LCtrl as u8);
-                    } else if code.is_normal_key() && self.i < self.report.keys.len() {
-                        self.report.keys[self.i] = code as u8;
-                        self.i += 1;
-                    }
+                Action::Key(code) => self.add_key(code),
+                Action::Shifted(code) => {
+                    self.report.modifiers |= KeyCode::LShift.modifier_bit();
+                    self.add_key(code);
                 }
+                Action::GraveEscape => self.grave_escape = true,
+                _ => {}
+            }
+        }
+    }
+
+    // Grave Escape depends on all the modifiers of this scan
+    fn finish(&mut self) {
+        if self.grave_escape {
+            let shift_or_meta = KeyCode::LShift.modifier_bit()
+                | KeyCode::RShift.modifier_bit()
+                | KeyCode::LMeta.modifier_bit()
+                | KeyCode::RMeta.modifier_bit();
+            if self.report.modifiers & shift_or_meta != 0 {
+                self.add_key(KeyCode::Grave);
+            } else {
+                self.add_key(KeyCode::Escape);
+            }
+        }
+    }
+}
+
+impl EventProcessor for Keymap {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if let (Action::SetKeymap(id), true, true) = (*action, pressed, changed) {
+            self.select(id);
+        }
+    }
+}
+
+impl EventProcessor for Steno {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if let (Action::Steno(key), true) = (*action, changed) {
+            self.press(key, pressed);
+        }
+    }
+}
+
+impl EventProcessor for MacroPlayer {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed && pressed {
+            match *action {
+                Action::Macro(id) => self.play(id),
+                Action::TypeVersion => self.type_text(VERSION),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl EventProcessor for DynamicMacro {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed && pressed {
+            match *action {
+                Action::DynamicMacroRecord => self.toggle_recording(),
+                Action::DynamicMacroPlay => self.play(),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl EventProcessor for UnicodeInput {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed && pressed {
+            match *action {
+                Action::Unicode(id) => self.type_char(id),
+                Action::SetUnicodeMode(mode) => self.mode = mode,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl EventProcessor for TypingStats {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed && pressed {
+            match *action {
+                Action::LedWpm => self.wpm_shown = !self.wpm_shown,
+                Action::LedHeatmap => self.heatmap_shown = !self.heatmap_shown,
                 _ => {}
             }
         }
@@ -162,13 +1431,20 @@ where
     fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
         if changed && pressed {
             let result = match *action {
-                Action::LedOn => self.theme_mode(),
-                Action::LedOff => self.set_theme(0),
+                Action::LedOn => self.light_on(),
+                Action::LedOff => self.light_off(),
                 Action::LedToggle => self.toggle(),
                 Action::LedNextTheme => self.next_theme(),
                 Action::LedNextBrightness => self.next_brightness(),
+                Action::LedPreviousBrightness => self.previous_brightness(),
                 Action::LedNextAnimationSpeed => self.next_animation_speed(),
+                Action::LedNextAnimation => self.next_animation(),
                 Action::LedTheme(theme_id) => self.set_theme(theme_id),
+                Action::LedRandomTheme => self.random_theme(),
+                Action::LedBrightness(level) => self.set_brightness(level),
+                Action::LedSpeed(level) => self.set_speed(level),
+                Action::LedAnimation(id) => self.set_animation(Animation::from_id(id)),
+                Action::LedTestPattern => self.test_pattern(),
                 _ => Ok(()),
             };
             result.log_error()
@@ -185,16 +1461,300 @@ where
             let result = match *action {
                 Action::BtOn => self.on(),
                 Action::BtOff => self.off(),
-                Action::BtSaveHost(host) => self.save_host(host),
-                Action::BtConnectHost(host) => self.connect_host(host),
+                Action::BtSaveHost(host) => self.enter_pairing(host),
+                Action::BtExitPairing => self.exit_pairing(),
+                Action::BtConnectHost(host) => self.select_profile(host),
                 Action::BtDeleteHost(host) => self.delete_host(host),
+                Action::BtDeleteAllHosts => self.delete_all_hosts(),
                 Action::BtBroadcast => self.broadcast(),
                 Action::BtCompatibilityMode(on) => self.enable_compatibility_mode(on),
                 Action::BtToggleCompatibilityMode => self.toggle_compatibility_mode(),
                 Action::BtHostListQuery => self.host_list_query(),
+                Action::BtPair(_) => self.pair(),
+                Action::LedBattery => self.query_battery(),
                 _ => Ok(()),
             };
             result.log_error()
         }
     }
 }
+
+impl<BUFFER> LayerHook<BUFFER> for Bluetooth<BUFFER>
+where
+    BUFFER: Unsize<[u8]>,
+{
+    fn layer_changed(&mut self, layer: u8, active: bool, led: &mut Led<BUFFER>) {
+        // the BT layer shows the Bluetooth status while it's on
+        if layer == LAYER_BT {
+            self.show_status(active, led).log_error()
+        }
+    }
+}
+
+/// Lights up the keys of the highest active layer from LAYER_COLORS in its
+/// color, as the active keymap maps them, on top of the current theme and the
+/// other overlays.
+struct LayerIndicator {
+    active: u16,
+    shown: Option<u8>,
+    lit: KeyState,
+}
+
+impl LayerIndicator {
+    const fn new() -> LayerIndicator {
+        LayerIndicator {
+            active: 0,
+            shown: None,
+            lit: [false; 70],
+        }
+    }
+
+    fn color(layer: u8) -> Option<Rgb> {
+        LAYER_COLORS
+            .iter()
+            .find(|&&(l, _)| l == layer)
+            .map(|&(_, color)| color)
+    }
+
+    fn layer_changed<BUFFER>(
+        &mut self,
+        layer: u8,
+        active: bool,
+        keymap: &Keymap,
+        led: &mut Led<BUFFER>,
+    ) where
+        BUFFER: Unsize<[u8]>,
+    {
+        if LayerIndicator::color(layer).is_none() {
+            return;
+        }
+        if active {
+            self.active |= 1 << layer;
+        } else {
+            self.active &= !(1 << layer);
+        }
+
+        let top = (0..LAYERS.len() as u8)
+            .rev()
+            .find(|layer| self.active & (1 << layer) != 0);
+        if top == self.shown {
+            return;
+        }
+        self.shown = top;
+
+        let color = top.and_then(LayerIndicator::color).unwrap_or(Rgb(0, 0, 0));
+        let mut released = false;
+        for index in 0..self.lit.len() {
+            let lit = match top.map(|layer| keymap.get(layer, index)) {
+                Some(Action::Transparent) | Some(Action::Key(KeyCode::No)) | None => false,
+                Some(_) => true,
+            };
+            if lit {
+                led.framebuffer().set_index(index, color, LedMode::On);
+            } else if self.lit[index] {
+                led.framebuffer().forget(index);
+                released = true;
+            }
+            self.lit[index] = lit;
+        }
+        // the theme only comes back on the keys of the layer shown before
+        if released {
+            led.refresh_theme().log_error();
+        } else {
+            led.flush().log_error();
+        }
+    }
+}
+
+/// Pulses the key that started recording or playing a dynamic macro, in
+/// MACRO_RECORD_COLOR or MACRO_PLAY_COLOR.
+struct MacroIndicator {
+    key: Option<usize>,
+    shown: bool,
+}
+
+impl MacroIndicator {
+    const fn new() -> MacroIndicator {
+        MacroIndicator {
+            key: None,
+            shown: false,
+        }
+    }
+
+    fn key_pressed(&mut self, key: usize, action: &Action) {
+        match *action {
+            Action::DynamicMacroRecord | Action::DynamicMacroPlay => self.key = Some(key),
+            _ => {}
+        }
+    }
+
+    fn update<BUFFER>(&mut self, dynamic_macro: &DynamicMacro, now: u32, led: &mut Led<BUFFER>)
+    where
+        BUFFER: Unsize<[u8]>,
+    {
+        let color = if dynamic_macro.is_recording() {
+            MACRO_RECORD_COLOR
+        } else if dynamic_macro.is_playing() {
+            MACRO_PLAY_COLOR
+        } else {
+            if self.shown {
+                self.shown = false;
+                led.theme_mode().log_error();
+            }
+            return;
+        };
+        let key = match self.key {
+            Some(key) => key,
+            None => return,
+        };
+        // a new level every few scans is smooth enough
+        if self.shown && now % ANIMATION_INTERVAL != 0 {
+            return;
+        }
+        self.shown = true;
+
+        let phase = now % MACRO_PULSE_PERIOD;
+        let half = MACRO_PULSE_PERIOD / 2;
+        let distance = (phase as i32 - half as i32).abs() as u32;
+        let level = (255 - distance * 255 / half.max(1)) as u8;
+        let color = scale(color, level);
+        led.framebuffer().set_index_now(key, color, LedMode::On);
+        led.flush().log_error();
+    }
+}
+
+/// Switches to the LAYER_THEMES theme of the highest active layer.
+struct LayerTheme {
+    active: u16,
+    shown: Option<u8>,
+    // the theme from before the first layer theme
+    restore: Option<u8>,
+}
+
+impl LayerTheme {
+    const fn new() -> LayerTheme {
+        LayerTheme {
+            active: 0,
+            shown: None,
+            restore: None,
+        }
+    }
+
+    fn theme(layer: u8) -> Option<u8> {
+        LAYER_THEMES
+            .iter()
+            .find(|&&(l, _)| l == layer)
+            .map(|&(_, theme)| theme)
+    }
+}
+
+impl<BUFFER> LayerHook<BUFFER> for LayerTheme
+where
+    BUFFER: Unsize<[u8]>,
+{
+    fn layer_changed(&mut self, layer: u8, active: bool, led: &mut Led<BUFFER>) {
+        if LayerTheme::theme(layer).is_none() {
+            return;
+        }
+        if active {
+            self.active |= 1 << layer;
+        } else {
+            self.active &= !(1 << layer);
+        }
+
+        let top = (0..LAYERS.len() as u8)
+            .rev()
+            .find(|layer| self.active & (1 << layer) != 0);
+        if top == self.shown {
+            return;
+        }
+        if self.shown.is_none() {
+            self.restore = led.config().theme;
+        }
+        self.shown = top;
+
+        let theme = top.and_then(LayerTheme::theme).or(self.restore);
+        match theme {
+            Some(theme) => led.set_theme(theme).log_error(),
+            None => led.theme_mode().log_error(),
+        }
+    }
+}
+
+struct ClockDisplay {
+    shown: bool,
+    // the time that's on the LEDs
+    time: Option<Time>,
+}
+
+impl ClockDisplay {
+    const fn new() -> ClockDisplay {
+        ClockDisplay {
+            shown: false,
+            time: None,
+        }
+    }
+
+    fn update<BUFFER>(&mut self, time: Time, led: &mut Led<BUFFER>)
+    where
+        BUFFER: Unsize<[u8]>,
+    {
+        if !self.shown {
+            if self.time.take().is_some() {
+                led.theme_mode().log_error();
+            }
+            return;
+        }
+        if self.time != Some(time) {
+            self.time = Some(time);
+            led.show_clock(time).log_error();
+        }
+    }
+}
+
+impl EventProcessor for ClockDisplay {
+    fn process(&mut self, action: &Action, pressed: bool, changed: bool) {
+        if changed && pressed && *action == Action::LedClock {
+            self.shown = !self.shown;
+        }
+    }
+}
+
+/// Dims the LEDs during NIGHT_HOURS.
+struct NightMode {
+    dimmed: bool,
+    // the brightness from before
+    restore: Option<u8>,
+}
+
+impl NightMode {
+    const fn new() -> NightMode {
+        NightMode {
+            dimmed: false,
+            restore: None,
+        }
+    }
+
+    fn update<BUFFER>(&mut self, time: Time, led: &mut Led<BUFFER>)
+    where
+        BUFFER: Unsize<[u8]>,
+    {
+        let night = match NIGHT_HOURS {
+            // the night can go past midnight
+            Some((start, end)) if start > end => time.hours >= start || time.hours < end,
+            Some((start, end)) => time.hours >= start && time.hours < end,
+            None => false,
+        };
+        if night == self.dimmed {
+            return;
+        }
+        self.dimmed = night;
+
+        if night {
+            self.restore = led.config().brightness;
+            led.set_brightness(NIGHT_BRIGHTNESS).log_error();
+        } else if let Some(level) = self.restore.take() {
+            led.set_brightness(level).log_error();
+        }
+    }
+}
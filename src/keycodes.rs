@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use core::mem::transmute;
+
 // USB HID KeyCodes
 #[derive(PartialOrd, PartialEq, Copy, Clone)]
 pub enum KeyCode {
@@ -106,6 +108,28 @@ pub enum KeyCode {
     NonUSBackslash, // Non-US \ and | (Typically near the Left-Shift key)
     Application,    // 0x65 - Max keycode the Bluetooth HID descriptor supports
 
+    // International keys, USB only
+    KpComma = 0x85, // Keypad , (. on Brazilian keyboards)
+    KpEqualAs400,
+    Ro,   // 0x87 International1, also / and ? on Brazilian keyboards
+    Kana, // Katakana/Hiragana
+    Yen,
+    Henkan,
+    Muhenkan,
+    KpJpComma,
+    International7,
+    International8,
+    International9,
+    Hangul, // 0x90 Lang1, Hangul/English, also Kana on Apple JIS keyboards
+    Hanja,  // Lang2, also Eisu on Apple JIS keyboards
+    Katakana,
+    Hiragana,
+    ZenkakuHankaku,
+    Lang6,
+    Lang7,
+    Lang8,
+    Lang9, // 0x98
+
     // Modifiers
     LCtrl = 0xE0,
     LShift,
@@ -118,17 +142,64 @@ pub enum KeyCode {
 }
 
 impl KeyCode {
+    /// The key with HID usage `code`, if there is one.
+    pub fn from_u8(code: u8) -> Option<KeyCode> {
+        let valid = code <= KeyCode::Application as u8
+            || (code >= KeyCode::KpComma as u8 && code <= KeyCode::Lang9 as u8)
+            || (code >= KeyCode::LCtrl as u8 && code <= KeyCode::RMeta as u8);
+        if valid {
+            Some(unsafe { transmute(code) })
+        } else {
+            None
+        }
+    }
+
     pub fn is_modifier(&self) -> bool {
         self >= &KeyCode::LCtrl && self <= &KeyCode::RMeta
     }
 
     pub fn is_normal_key(&self) -> bool {
-        self >= &KeyCode::A && self <= &KeyCode::Application
+        (self >= &KeyCode::A && self <= &KeyCode::Application) || self.is_international()
+    }
+
+    pub fn is_international(&self) -> bool {
+        self >= &KeyCode::KpComma && self <= &KeyCode::Lang9
+    }
+
+    /// Bit of this modifier in the HID report's modifier byte.
+    pub fn modifier_bit(&self) -> u8 {
+        1 << (*self as u8 - KeyCode::LCtrl as u8)
     }
 }
 
+// Matrix positions without a key
+pub const NO_KEYS: [KeyIndex; 9] = [
+    KeyIndex::No1,
+    KeyIndex::No2,
+    KeyIndex::No3,
+    KeyIndex::No4,
+    KeyIndex::No5,
+    KeyIndex::No6,
+    KeyIndex::No7,
+    KeyIndex::No8,
+    KeyIndex::No9,
+];
+
+// Where the center of each KeyIndex is on the board, as (x, y) in quarters of
+// a key width from the top left. Matrix positions without a key sit under the
+// wide key next to them.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub const KEY_POSITIONS: [(u8, u8); 70] = [
+    (2, 2), (6, 2), (10, 2), (14, 2), (18, 2), (22, 2), (26, 2), (30, 2), (34, 2), (38, 2), (42, 2), (46, 2), (50, 2), (56, 2),
+    (3, 6), (8, 6), (12, 6), (16, 6), (20, 6), (24, 6), (28, 6), (32, 6), (36, 6), (40, 6), (44, 6), (48, 6), (52, 6), (57, 6),
+    (4, 10), (9, 10), (13, 10), (17, 10), (21, 10), (25, 10), (29, 10), (33, 10), (37, 10), (41, 10), (45, 10), (49, 10), (56, 10), (56, 10),
+    (4, 14), (11, 14), (15, 14), (19, 14), (23, 14), (27, 14), (31, 14), (35, 14), (39, 14), (43, 14), (47, 14), (54, 14), (54, 14), (54, 14),
+    (2, 18), (8, 18), (12, 18), (28, 18), (28, 18), (28, 18), (28, 18), (28, 18), (28, 18), (28, 18), (42, 18), (48, 18), (52, 18), (58, 18),
+];
+
 // Index of each physical Key in the scan matrix
 #[cfg_attr(rustfmt, rustfmt_skip)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum KeyIndex {
     Escape,   N1,    N2,   N3,  N4,  N5,    N6,  N7,  N8,     N9,  N0,      Minus,    Equal,    BSpace,
     Tab,      Q,     W,    E,   R,   T,     Y,   U,   I,      O,   P,       LBracket, RBracket, BSlash,
@@ -0,0 +1,33 @@
+use action::Action;
+use keymatrix::COLUMNS;
+use layout::KEYMAPS;
+use settings::Settings;
+
+/// The active keymap from layout::KEYMAPS.
+///
+/// It's kept in the settings store, so it survives power cycles.
+pub struct Keymap {
+    pub active: u8,
+}
+
+impl Keymap {
+    pub const fn new() -> Keymap {
+        Keymap { active: 0 }
+    }
+
+    pub fn load(&mut self, settings: &Settings) {
+        self.select(settings.active_keymap());
+    }
+
+    pub fn get(&self, layer: u8, key: usize) -> Action {
+        KEYMAPS[self.active as usize][layer as usize][key / COLUMNS][key % COLUMNS]
+    }
+
+    /// Switches to keymap `id`, returns false if there's no such keymap.
+    pub fn select(&mut self, id: u8) -> bool {
+        if (id as usize) < KEYMAPS.len() {
+            self.active = id;
+        }
+        (id as usize) < KEYMAPS.len()
+    }
+}
@@ -6,8 +6,8 @@ use hal::gpio::gpioa::*;
 use hal::gpio::gpiob::*;
 use stm32l151::SYST;
 
-const ROWS: usize = 5;
-const COLUMNS: usize = 14;
+pub const ROWS: usize = 5;
+pub const COLUMNS: usize = 14;
 
 type RowPins = (PB9<Input>, PB8<Input>, PB7<Input>, PB6<Input>, PA0<Input>);
 type ColumnPins = (
@@ -51,6 +51,10 @@ pub fn to_packed_bits(state: &KeyState) -> PackedKeyState {
 pub struct KeyMatrix {
     /// Stores the currently pressed down keys from last sample.
     pub state: KeyState,
+    /// Number of samples taken so far, used as timestamp for key events.
+    pub ticks: u32,
+    /// Tick of the sample in which each key was last pressed down.
+    pub pressed_at: [u32; ROWS * COLUMNS],
     row_pins: RowPins,
     column_pins: ColumnPins,
 }
@@ -59,12 +63,16 @@ impl KeyMatrix {
     pub fn new(row_pins: RowPins, column_pins: ColumnPins) -> Self {
         Self {
             state: [false; ROWS * COLUMNS],
+            ticks: 0,
+            pressed_at: [0; ROWS * COLUMNS],
             row_pins,
             column_pins,
         }
     }
 
     pub fn sample(&mut self, syst: &SYST) {
+        self.ticks = self.ticks.wrapping_add(1);
+
         for column in 0..COLUMNS {
             self.enable_column(column);
 
@@ -74,16 +82,33 @@ impl KeyMatrix {
             let wait_until_tick = current_tick - 100;
             while syst.cvr.read() > wait_until_tick {}
 
-            self.state[column] = self.row_pins.0.is_high();
-            self.state[column + COLUMNS] = self.row_pins.1.is_high();
-            self.state[column + 2 * COLUMNS] = self.row_pins.2.is_high();
-            self.state[column + 3 * COLUMNS] = self.row_pins.3.is_high();
-            self.state[column + 4 * COLUMNS] = self.row_pins.4.is_high();
+            let rows = [
+                self.row_pins.0.is_high(),
+                self.row_pins.1.is_high(),
+                self.row_pins.2.is_high(),
+                self.row_pins.3.is_high(),
+                self.row_pins.4.is_high(),
+            ];
+            for (row, pressed) in rows.iter().enumerate() {
+                self.update(column + row * COLUMNS, *pressed);
+            }
 
             self.disable_column(column);
         }
     }
 
+    /// Number of ticks since the key was last pressed down.
+    pub fn held_for(&self, key: usize) -> u32 {
+        self.ticks.wrapping_sub(self.pressed_at[key])
+    }
+
+    fn update(&mut self, key: usize, pressed: bool) {
+        if pressed && !self.state[key] {
+            self.pressed_at[key] = self.ticks;
+        }
+        self.state[key] = pressed;
+    }
+
     fn enable_column(&mut self, column: usize) {
         match column {
             0 => self.column_pins.0.set_high(),
@@ -1,6 +1,21 @@
 use action::Action;
 use action::Action::*;
+use combo::Combo;
+use compose::ComposeSequence;
+use framebuffer::Rgb;
+use gamma::Gamma;
+use key_override::KeyOverride;
+use keyboard::TapHoldPolicy;
+use keycodes::KeyCode;
 use keycodes::KeyCode::*;
+use keycodes::KeyIndex;
+use keymatrix::{COLUMNS, ROWS};
+use leader::LeaderSequence;
+use macros::MacroStep;
+use steno::StenoKey;
+use tapdance::TapDance;
+use theme::ThemeFrame;
+use unicode::UnicodeMode;
 
 /*
   ,-----------------------------------------------------------------------------.
@@ -16,13 +31,249 @@ use keycodes::KeyCode::*;
   `-----------------------------------------------------------------------------'
 */
 
-pub type Layout = [Action; 70];
+pub type Layout = [[Action; COLUMNS]; ROWS];
 
-pub const LAYERS: [Layout; 4] = [BASE, FN, FN2, BT];
+// All layers of a keymap
+pub type Layouts = [Layout; 9];
 
-pub const LAYER_FN: u8 = 1;
-pub const LAYER_FN2: u8 = 2;
-pub const LAYER_BT: u8 = 3;
+pub const LAYERS: Layouts = [BASE, COLEMAK, ARROWS, FN, FN2, BT, ADJUST, NUMPAD, STENO];
+pub const MAC_LAYERS: Layouts = [
+    MAC_BASE, COLEMAK, ARROWS, FN, FN2, BT, ADJUST, NUMPAD, STENO,
+];
+
+// Keymaps to pick from with SetKeymap, the first one is the default
+pub const KEYMAPS: [Layouts; 2] = [LAYERS, MAC_LAYERS];
+
+pub const LAYER_BASE: u8 = 0;
+pub const LAYER_COLEMAK: u8 = 1;
+pub const LAYER_ARROWS: u8 = 2;
+pub const LAYER_FN: u8 = 3;
+pub const LAYER_FN2: u8 = 4;
+pub const LAYER_BT: u8 = 5;
+pub const LAYER_ADJUST: u8 = 6;
+pub const LAYER_NUMPAD: u8 = 7;
+pub const LAYER_STENO: u8 = 8;
+
+// The third layer is on whenever both of the first two are
+pub const TRI_LAYER: (u8, u8, u8) = (LAYER_FN, LAYER_FN2, LAYER_ADJUST);
+
+// Timings are counted in matrix scans, one scan happens about every 3ms.
+// Dual-role keys released before TAPPING_TERM count as tap, otherwise as hold.
+pub const TAPPING_TERM: u32 = 64;
+// Keys that need a different tapping term, e.g. slower pinky keys
+pub const TAPPING_TERMS: &[(KeyIndex, u32)] = &[(KeyIndex::Capslock, 80)];
+// Dual-role keys that still tap when released after TAPPING_TERM, as long as
+// no other key was pressed
+pub const RETRO_TAPPING: &[KeyIndex] = &[KeyIndex::Capslock];
+// Lets dual-role keys count as held before TAPPING_TERM, see TapHoldPolicy
+pub const TAP_HOLD_POLICY: TapHoldPolicy = TapHoldPolicy::PermissiveHold;
+// Pending one-shot modifiers are dropped if no key follows within this time.
+pub const ONESHOT_TIMEOUT: u32 = 1600;
+// Latched one-shot modifier and layer keys light up in this color.
+pub const ONESHOT_COLOR: Rgb = Rgb(0x00, 0xff, 0xff);
+// With Auto Shift on, keys held at least this long are sent shifted.
+pub const AUTO_SHIFT_TERM: u32 = 58;
+
+// LED animations draw a frame every ANIMATION_INTERVAL scans, about 30 times
+// a second, each frame goes out as three messages to the LED controller.
+pub const ANIMATION_INTERVAL: u32 = 11;
+pub const ANIMATION_COLOR: Rgb = Rgb(0x00, 0x80, 0xff);
+// The other end of the Gradient animation.
+pub const GRADIENT_COLOR: Rgb = Rgb(0xff, 0x00, 0x80);
+// Keys fade to new colors over this many frames of ANIMATION_INTERVAL scans.
+pub const TRANSITION_FRAMES: u8 = 4;
+// Curve applied to all colors before they're sent to the LED controller.
+pub const GAMMA: Gamma = Gamma::Gamma22;
+// While one of these layers is active, its keys light up in its color. The
+// highest active layer wins.
+pub const LAYER_COLORS: &[(u8, Rgb)] = &[
+    (LAYER_FN, Rgb(0x00, 0x00, 0xff)),
+    (LAYER_FN2, Rgb(0x00, 0xff, 0x00)),
+    (LAYER_ADJUST, Rgb(0xff, 0x00, 0x00)),
+    (LAYER_NUMPAD, Rgb(0xff, 0xff, 0x00)),
+    (LAYER_STENO, Rgb(0xff, 0x00, 0xff)),
+];
+// While one of these layers is active the LED controller switches to its
+// theme, e.g. (LAYER_FN, 2). The highest active layer wins, the theme from
+// before comes back once none is left.
+pub const LAYER_THEMES: &[(u8, u8)] = &[];
+// Keys lit up in MODIFIER_COLOR while their modifier is held, locked with
+// KeyLock or latched by a one-shot key.
+pub const MODIFIER_LEDS: &[(KeyCode, KeyIndex)] = &[
+    (LShift, KeyIndex::LShift),
+    (LCtrl, KeyIndex::LCtrl),
+    (LMeta, KeyIndex::LMeta),
+    (LAlt, KeyIndex::LAlt),
+    (RAlt, KeyIndex::RAlt),
+    (RCtrl, KeyIndex::RCtrl),
+    (RShift, KeyIndex::RShift),
+];
+pub const MODIFIER_COLOR: Rgb = Rgb(0xff, 0x80, 0x00);
+
+// Uploaded into the LED controller's user theme slot at startup, as frames of
+// per-key colors.
+pub const USER_THEME: Option<(u8, &[ThemeFrame])> = None;
+
+// The typing speed on the number row is updated every WPM_UPDATE_INTERVAL
+// scans (about a second), every key stands for WPM_PER_KEY words per minute.
+pub const WPM_UPDATE_INTERVAL: u32 = 333;
+pub const WPM_PER_KEY: u32 = 15;
+// Save the press counts of the typing heatmap whenever it's toggled, so they
+// survive power cycles.
+pub const HEATMAP_PERSIST: bool = false;
+
+// The LEDs go off after this many scans without a key press and come back
+// with the next one, 0 keeps them on.
+pub const LED_IDLE_TIMEOUT: u32 = 100_000;
+
+// From the first hour until the second, by the clock, the LEDs are dimmed to
+// NIGHT_BRIGHTNESS and get their brightness back afterwards. None turns this
+// off.
+pub const NIGHT_HOURS: Option<(u8, u8)> = None;
+pub const NIGHT_BRIGHTNESS: u8 = 1;
+
+// Keys lit up by the keyboard are scaled to their level (0 - 255) here, e.g.
+// (KeyIndex::Q, 64). Keys left out stay at full brightness, so indicators can
+// stand out from a dimmed board.
+pub const KEY_BRIGHTNESS: &[(KeyIndex, u8)] = &[];
+
+// At power-up, sweep across the keys and show the firmware version on the
+// number, top and home rows for SPLASH_VERSION_TIME scans.
+pub const BOOT_SPLASH: bool = true;
+pub const SPLASH_VERSION_TIME: u32 = 333;
+
+// The pressed keys go to the LED controller at most every this many scans.
+pub const KEY_STATE_INTERVAL: u32 = 5;
+
+// The battery level stays on the number row for this many scans.
+pub const BATTERY_TIMEOUT: u32 = 1000;
+
+// When the link to a host drops, the keyboard tries to reconnect after
+// RECONNECT_DELAY scans (about a second), waiting twice as long after every
+// failed attempt. After RECONNECT_ATTEMPTS it broadcasts instead, 0 never
+// reconnects.
+pub const RECONNECT_DELAY: u32 = 333;
+pub const RECONNECT_ATTEMPTS: u8 = 5;
+
+// While a dynamic macro is recorded or played back, the key that started it
+// pulses in these colors, once every this many scans.
+pub const MACRO_RECORD_COLOR: Rgb = Rgb(0xff, 0x00, 0x00);
+pub const MACRO_PLAY_COLOR: Rgb = Rgb(0x00, 0xff, 0x00);
+pub const MACRO_PULSE_PERIOD: u32 = 333;
+
+// The LED test pattern shows each color on each key for this many scans.
+pub const TEST_PATTERN_TIME: u32 = 50;
+
+// Keys lit up by the reactive animation fade out over this many frames.
+pub const REACTIVE_FADE_FRAMES: u8 = 8;
+
+// A leader sequence ends when no key was typed for this long.
+pub const LEADER_TIMEOUT: u32 = 160;
+
+// Typed after the Leader key
+pub const LEADER_SEQUENCES: &[LeaderSequence] = &[
+    LeaderSequence {
+        keys: &[L, O],
+        action: LedToggle,
+    },
+    LeaderSequence {
+        keys: &[L, T],
+        action: LedNextTheme,
+    },
+    LeaderSequence {
+        keys: &[B, T],
+        action: LayerOn(LAYER_BT),
+    },
+    LeaderSequence {
+        keys: &[A, N],
+        action: Macro(0),
+    },
+];
+
+// Presses of combo keys are held back this long to wait for the other key.
+pub const COMBO_TERM: u32 = 10;
+
+pub const COMBO_COUNT: usize = 1;
+pub const COMBOS: [Combo; COMBO_COUNT] = [
+    // J + K for Escape
+    Combo {
+        keys: [KeyIndex::J, KeyIndex::K],
+        action: Key(Escape),
+    },
+];
+
+// Applied to every report
+pub const KEY_OVERRIDES: &[KeyOverride] = &[
+    // Shift + Backspace for Delete
+    KeyOverride {
+        modifiers: &[LShift, RShift],
+        key: BSpace,
+        replacement: Delete,
+    },
+];
+
+// Column each key takes its action from while SwapHands is held
+pub const SWAP_HANDS: [[u8; COLUMNS]; ROWS] = [
+    [13, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 11, 12, 0],
+    [13, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 11, 12, 0],
+    [13, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 11, 12, 0],
+    [13, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 11, 12, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13],
+];
+
+// Use as Macro(index) in a layout
+pub const MACROS: &[&[MacroStep]] = &[
+    // types "Anne"
+    &[
+        MacroStep::Modified(LShift, A),
+        MacroStep::Tap(N),
+        MacroStep::Tap(N),
+        MacroStep::Tap(E),
+    ],
+];
+
+// Use as Unicode(index) in a layout
+pub const UNICODE: &[char] = &['€', '£', '°', '→'];
+
+// Compose gives up when the second key doesn't follow within this time.
+pub const COMPOSE_TIMEOUT: u32 = 330;
+
+// Typed after the Compose key, sent with the Unicode input method
+pub const COMPOSE_SEQUENCES: &[ComposeSequence] = &[
+    ComposeSequence {
+        keys: (Quote, E),
+        character: 'é',
+    },
+    ComposeSequence {
+        keys: (Quote, A),
+        character: 'á',
+    },
+    ComposeSequence {
+        keys: (Grave, E),
+        character: 'è',
+    },
+    ComposeSequence {
+        keys: (Grave, A),
+        character: 'à',
+    },
+    ComposeSequence {
+        keys: (Comma, C),
+        character: 'ç',
+    },
+    ComposeSequence {
+        keys: (S, S),
+        character: 'ß',
+    },
+];
+
+// Use as TapDance(index) in a layout
+pub const TAP_DANCE_COUNT: usize = 1;
+pub const TAP_DANCES: [TapDance; TAP_DANCE_COUNT] = [
+    // Shift, double tap for Caps Lock
+    TapDance {
+        actions: [Key(LShift), Key(Capslock), Nop],
+    },
+];
 
 // activate by indexing into LAYERS
 const FN_M: Action = LayerMomentary(LAYER_FN);
@@ -31,37 +282,170 @@ const __: Action = Transparent;
 const LED_NT: Action = LedNextTheme;
 const LED_NB: Action = LedNextBrightness;
 const LED_NAS: Action = LedNextAnimationSpeed;
+const LED_PB: Action = LedPreviousBrightness;
+const LED_ANI: Action = LedNextAnimation;
+const LED_BAT: Action = LedBattery;
+const LED_WPM: Action = LedWpm;
+const LED_HEAT: Action = LedHeatmap;
+const LED_CLK: Action = LedClock;
+const LED_RND: Action = LedRandomTheme;
+const LED_TST: Action = LedTestPattern;
 const BT_ON: Action = LayerOn(LAYER_BT);
+const DM_REC: Action = DynamicMacroRecord;
+const DM_PLY: Action = DynamicMacroPlay;
+const CAPS_CTRL: Action = ModTap(LCtrl, Escape);
+const AS_T: Action = AutoShiftToggle;
+const CW: Action = CapsWord;
+const GESC: Action = GraveEscape;
+const LLOCK: Action = LayerLock;
+const EURO: Action = Unicode(0);
+const NUM_T: Action = LayerToggle(LAYER_NUMPAD);
+const GAME: Action = GamingMode;
+const UC_LNX: Action = SetUnicodeMode(UnicodeMode::Linux);
+const UC_MAC: Action = SetUnicodeMode(UnicodeMode::MacOs);
+const UC_WIN: Action = SetUnicodeMode(UnicodeMode::WinCompose);
+const DF_QWER: Action = DefaultLayer(LAYER_BASE);
+const DF_COLE: Action = DefaultLayer(LAYER_COLEMAK);
+const KLOCK: Action = KeyLock;
+const KM_PC: Action = SetKeymap(0);
+const KM_MAC: Action = SetKeymap(1);
+const ARR_T: Action = LayerToggle(LAYER_ARROWS);
+const RSFT_UP: Action = ModTap(RShift, Up);
+const FN_LEFT: Action = LayerTap(LAYER_FN, Left);
+const FN2_DOWN: Action = LayerTap(LAYER_FN2, Down);
+const RCTL_RIGHT: Action = ModTap(RCtrl, Right);
+const STENO_T: Action = LayerToggle(LAYER_STENO);
+const BT_1: Action = BtProfile(0, F1);
+const BT_2: Action = BtProfile(1, F2);
+const BT_3: Action = BtProfile(2, F3);
+const BT_4: Action = BtProfile(3, F4);
+const BT_PAIR: Action = BtPair(F10);
+// Steno keys, an L or R at the end marks the left or right hand side
+const ST_N1: Action = Steno(StenoKey::Num1);
+const ST_N2: Action = Steno(StenoKey::Num2);
+const ST_N3: Action = Steno(StenoKey::Num3);
+const ST_N4: Action = Steno(StenoKey::Num4);
+const ST_N5: Action = Steno(StenoKey::Num5);
+const ST_N6: Action = Steno(StenoKey::Num6);
+const ST_N7: Action = Steno(StenoKey::Num7);
+const ST_N8: Action = Steno(StenoKey::Num8);
+const ST_N9: Action = Steno(StenoKey::Num9);
+const ST_NA: Action = Steno(StenoKey::NumA);
+const ST_NB: Action = Steno(StenoKey::NumB);
+const ST_NC: Action = Steno(StenoKey::NumC);
+const ST_S1: Action = Steno(StenoKey::S1);
+const ST_S2: Action = Steno(StenoKey::S2);
+const ST_TL: Action = Steno(StenoKey::TL);
+const ST_KL: Action = Steno(StenoKey::KL);
+const ST_PL: Action = Steno(StenoKey::PL);
+const ST_WL: Action = Steno(StenoKey::WL);
+const ST_HL: Action = Steno(StenoKey::HL);
+const ST_RL: Action = Steno(StenoKey::RL);
+const ST_A: Action = Steno(StenoKey::A);
+const ST_O: Action = Steno(StenoKey::O);
+const ST_ST1: Action = Steno(StenoKey::Star1);
+const ST_ST2: Action = Steno(StenoKey::Star2);
+const ST_ST3: Action = Steno(StenoKey::Star3);
+const ST_ST4: Action = Steno(StenoKey::Star4);
+const ST_E: Action = Steno(StenoKey::E);
+const ST_U: Action = Steno(StenoKey::U);
+const ST_FR: Action = Steno(StenoKey::FR);
+const ST_RR: Action = Steno(StenoKey::RR);
+const ST_PR: Action = Steno(StenoKey::PR);
+const ST_BR: Action = Steno(StenoKey::BR);
+const ST_LR: Action = Steno(StenoKey::LR);
+const ST_GR: Action = Steno(StenoKey::GR);
+const ST_TR: Action = Steno(StenoKey::TR);
+const ST_SR: Action = Steno(StenoKey::SR);
+const ST_DR: Action = Steno(StenoKey::DR);
+const ST_ZR: Action = Steno(StenoKey::ZR);
 
 pub const BASE: Layout = layout![
-    Escape   N1     N2   N3 N4 N5    N6 N7 N8    N9  N0     Minus    Equal     BSpace
-    Tab      Q      W    E  R  T     Y  U  I     O   P      LBracket RBracket  BSlash
-    Capslock A      S    D  F  G     H  J  K     L   SColon Quote    No        Enter
-    LShift   Z      X    C  V  B     N  M  Comma Dot Slash  No       No        RShift
-    LCtrl    LMeta  LAlt No No Space No No No    No  RAlt   FN_M     FN2_M     RCtrl
+    [GESC      N1     N2   N3 N4 N5    N6 N7 N8    N9  N0     Minus    Equal     BSpace]
+    [Tab       Q      W    E  R  T     Y  U  I     O   P      LBracket RBracket  BSlash]
+    [CAPS_CTRL A      S    D  F  G     H  J  K     L   SColon Quote    No        Enter]
+    [LShift    Z      X    C  V  B     N  M  Comma Dot Slash  No       No        RShift]
+    [LCtrl     LMeta  LAlt No No Space No No No    No  RAlt   FN_M     FN2_M     RCtrl]
+];
+
+// BASE with Command next to Space and Option next to it
+pub const MAC_BASE: Layout = layout![
+    [GESC      N1     N2    N3 N4 N5    N6 N7 N8    N9  N0     Minus    Equal     BSpace]
+    [Tab       Q      W     E  R  T     Y  U  I     O   P      LBracket RBracket  BSlash]
+    [CAPS_CTRL A      S     D  F  G     H  J  K     L   SColon Quote    No        Enter]
+    [LShift    Z      X     C  V  B     N  M  Comma Dot Slash  No       No        RShift]
+    [LCtrl     LAlt   LMeta No No Space No No No    No  RMeta  FN_M     FN2_M     RCtrl]
+];
+
+// Default layer on top of BASE, picked with DefaultLayer
+pub const COLEMAK: Layout = layout![
+    [__ __ __ __ __ __ __ __ __ __ __     __ __ __]
+    [__ Q  W  F  P  G  J  L  U  Y  SColon __ __ __]
+    [__ A  R  S  T  D  H  N  E  I  O      __ No __]
+    [__ Z  X  C  V  B  K  M  __ __ __     No No __]
+    [__ __ __ No No __ No No No No __     __ __ __]
+];
+
+// Magic arrows like the stock firmware: tapping right Shift, Fn, Anne or right
+// Ctrl sends an arrow key, holding them works as usual. Fn + Anne + right
+// Shift toggles it.
+pub const ARROWS: Layout = layout![
+    [__ __ __ __ __ __ __ __ __ __ __ __      __       __]
+    [__ __ __ __ __ __ __ __ __ __ __ __      __       __]
+    [__ __ __ __ __ __ __ __ __ __ __ __      No       __]
+    [__ __ __ __ __ __ __ __ __ __ __ No      No       RSFT_UP]
+    [__ __ __ No No __ No No No No __ FN_LEFT FN2_DOWN RCTL_RIGHT]
 ];
 
 pub const FN: Layout = layout![
-  Grave F1   F2   F3    F4        F5      F6     F7     F8   F9         F10    F11    F12 __
-  __    __   Up   __    LedToggle LED_NAS LED_NB LED_NT Up   Scrolllock Pause  Home   End PScreen
-  __    Left Down Right __        __      __     Left   Down Right      PgUp   PgDown No  __
-  __    __   __   __    __        BT_ON   __     __     __   Insert     Delete No     No  __
-  __    __   __   No    No        __      No     No     No   No         __     __     __  __
+  [Grave BT_1 BT_2 BT_3  BT_4   F5      F6     F7     F8   F9         BT_PAIR F11    F12 __]
+  [__    __   Up   __    DM_REC LED_NAS LED_NB LED_NT Up   Scrolllock DM_PLY  Home   End PScreen]
+  [__    Left Down Right __     __      __     Left   Down Right      PgUp    PgDown No  __]
+  [CW    __   __   __    __     BT_ON   NUM_T  __     __   Insert     Delete  No     No  __]
+  [__    __   __   No    No     Leader  No     No     No   No         LLOCK   __     __  Compose]
 ];
 
 pub const FN2: Layout = layout![
-    LedOff LedOn LED_NT LED_NAS LED_NB __ __ __ __ __ __ __ __ __
-    __     __    __     __      __     __ __ __ __ __ __ __ __ __
-    __     __    __     __      __     __ __ __ __ __ __ __ No __
-    __     __    __     __      __     __ __ __ __ __ __ __ __ __
-    __     __    __     No      No     __ No No No No __ __ __ __
+    [LedOff LedOn    LED_NT  LED_NAS LED_NB    LED_PB  __ __ __ __ __    __ __ __]
+    [__     LED_HEAT LED_WPM LED_ANI LedToggle LED_RND __ __ __ __ Pause __ __ __]
+    [__     AS_T     __      __      __        __      __ __ __ __ __    __ No __]
+    [__     __       __      LED_CLK __        LED_BAT __ __ __ __ __    __ __ __]
+    [__     __       __      No      No        __      No No No No LLOCK __ __ __]
 ];
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 pub const BT: Layout = layout![
-    LayerOff(LAYER_BT) BtConnectHost(0) BtConnectHost(1) BtConnectHost(2) BtConnectHost(3) __ __ __ __ __ BtToggleCompatibilityMode BtOff BtBroadcast BtOn
-    __ BtSaveHost(0) BtSaveHost(1) BtSaveHost(2) BtSaveHost(3) __ __ __ __ __ __ __ __ __
-    __ BtDeleteHost(0) BtDeleteHost(1) BtDeleteHost(2) BtDeleteHost(3) __ __ __ __ __ __ __ No __
-    __ __ __ __ __ LayerOff(LAYER_BT) __ __ __ __ __ __ __ __
-    BtHostListQuery __ __ No No __ No No No No __ __ __ __
+    [LayerOff(LAYER_BT) BtConnectHost(0) BtConnectHost(1) BtConnectHost(2) BtConnectHost(3) __ __ __ __ __ BtToggleCompatibilityMode BtOff BtBroadcast BtOn]
+    [BtExitPairing BtSaveHost(0) BtSaveHost(1) BtSaveHost(2) BtSaveHost(3) __ __ __ __ __ __ __ __ __]
+    [BtDeleteAllHosts BtDeleteHost(0) BtDeleteHost(1) BtDeleteHost(2) BtDeleteHost(3) __ __ __ __ __ __ __ No __]
+    [__ __ __ __ __ LayerOff(LAYER_BT) __ __ __ __ __ __ __ __]
+    [BtHostListQuery __ __ No No __ No No No No __ __ __ __]
+];
+
+// Fn + Anne
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub const ADJUST: Layout = layout![
+    [__ __ __ __ __ __ __ __ __ __ __ __ __ __]
+    [__ DF_QWER __ EURO __ STENO_T __ __ __ __ __ __ __ __]
+    [__ AS_T __ LED_TST __ GAME PrivacyMode __ DF_COLE KLOCK __ __ No __]
+    [CW UC_LNX UC_MAC UC_WIN TypeVersion BT_ON KM_PC KM_MAC __ __ __ __ __ ARR_T]
+    [__ __ __ No No __ No No No No LLOCK __ __ __]
+];
+
+// Fn + N toggles it
+pub const NUMPAD: Layout = layout![
+    [__ __ __ __ __ __ Numlock Kp7 Kp8 Kp9   KpSlash    __ __ __]
+    [__ __ __ __ __ __ __      Kp4 Kp5 Kp6   KpAsterisk __ __ __]
+    [__ __ __ __ __ __ __      Kp1 Kp2 Kp3   KpMinus    __ No KpEnter]
+    [__ __ __ __ __ __ __      Kp0 Kp0 KpDot KpPlus     No No __]
+    [__ __ __ No No __ No      No  No  No    __         __ __ __]
+];
+
+// Plover's QWERTY steno layout, Fn + Anne + T toggles it and Esc leaves it
+pub const STENO: Layout = layout![
+    [STENO_T ST_N1 ST_N2 ST_N3 ST_N4 ST_N5  ST_N6  ST_N7 ST_N8 ST_N9 ST_NA ST_NB ST_NC No]
+    [No      ST_S1 ST_TL ST_PL ST_HL ST_ST1 ST_ST2 ST_FR ST_PR ST_LR ST_TR ST_DR No    No]
+    [No      ST_S2 ST_KL ST_WL ST_RL ST_ST3 ST_ST4 ST_RR ST_BR ST_GR ST_SR ST_ZR No    No]
+    [__      No    No    ST_A  ST_O  No     ST_E   ST_U  No    No    No    No    No    __]
+    [__      __    __    No    No    No     No     No    No    No    __    __    __    __]
 ];
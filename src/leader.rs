@@ -0,0 +1,90 @@
+use action::Action;
+use keycodes::KeyCode;
+use layout::{LEADER_SEQUENCES, LEADER_TIMEOUT};
+
+const MAX_SEQUENCE: usize = 4;
+
+/// Keys typed after the leader key and the action they trigger.
+pub struct LeaderSequence {
+    pub keys: &'static [KeyCode],
+    pub action: Action,
+}
+
+pub struct Leader {
+    active: bool,
+    sequence: [KeyCode; MAX_SEQUENCE],
+    len: usize,
+    since: u32,
+    releasing: Option<Action>,
+}
+
+impl Leader {
+    pub const fn new() -> Leader {
+        Leader {
+            active: false,
+            sequence: [KeyCode::No; MAX_SEQUENCE],
+            len: 0,
+            since: 0,
+            releasing: None,
+        }
+    }
+
+    /// Takes key presses while a sequence is being entered.
+    ///
+    /// Returns true if the press was captured and must not be sent.
+    pub fn capture(&mut self, action: &Action, now: u32) -> bool {
+        match *action {
+            Action::Leader => {
+                self.active = true;
+                self.len = 0;
+                self.since = now;
+                true
+            }
+            Action::Key(code) if self.active && !code.is_modifier() => {
+                if self.len < MAX_SEQUENCE {
+                    self.sequence[self.len] = code;
+                    self.len += 1;
+                }
+                self.since = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the (action, pressed, changed) event for this scan once the
+    /// sequence is complete: the matched action is tapped.
+    pub fn poll(&mut self, now: u32) -> Option<(Action, bool, bool)> {
+        if let Some(action) = self.releasing.take() {
+            return Some((action, false, true));
+        }
+
+        if !self.active {
+            return None;
+        }
+
+        let timed_out = now.wrapping_sub(self.since) >= LEADER_TIMEOUT;
+        if !timed_out && !self.is_final() {
+            return None;
+        }
+
+        self.active = false;
+        let entered = &self.sequence[..self.len];
+        match LEADER_SEQUENCES.iter().find(|s| s.keys == entered) {
+            Some(sequence) => {
+                self.releasing = Some(sequence.action);
+                Some((sequence.action, true, true))
+            }
+            None => None,
+        }
+    }
+
+    // No sequence can match anymore, or the entered one can't be extended
+    fn is_final(&self) -> bool {
+        let entered = &self.sequence[..self.len];
+        self.len > 0
+            && !LEADER_SEQUENCES
+                .iter()
+                .any(|s| s.keys.len() > self.len && s.keys.starts_with(entered))
+    }
+}
@@ -1,27 +1,190 @@
-use super::keymatrix::{to_packed_bits, KeyState};
+use super::keymatrix::{to_packed_bits, KeyState, COLUMNS, ROWS};
 use super::protocol::{LedOp, Message, MsgType};
 use super::serial::{Serial, Transfer};
 use super::serial::led_usart::LedUsart;
-use bluetooth::BluetoothMode;
+use animation::{Animation, Animator, Splash, TestPattern};
+use bluetooth::{BluetoothMode, BluetoothStatus, Pairing, HOSTS};
 use core::marker::Unsize;
+use debug::UnwrapLog;
 use embedded_hal::digital::OutputPin;
+use framebuffer::{Framebuffer, KeyColorFrame, Rgb, Snapshot, KEYS};
+use gamma::Gamma;
 use hal::gpio::{Input, Output};
 use hal::gpio::gpioc::PC15;
-use keycodes::KeyIndex;
+use keycodes::{KeyIndex, NO_KEYS};
+use layout::{
+    ANIMATION_INTERVAL, BATTERY_TIMEOUT, KEY_BRIGHTNESS, KEY_STATE_INTERVAL, LED_IDLE_TIMEOUT,
+    WPM_PER_KEY,
+};
 use nb;
+use rtc::Time;
 use rtfm::Threshold;
+use stats::TypingStats;
+use theme::{ThemeFrame, ThemeUpload, MAX_BLOCK_LEN};
 
+// Messages that find the send buffer busy wait here, for the next
+// TX-complete interrupt. Their data has to fit into the 128 byte send buffer
+// next to the header.
+const QUEUE_SIZE: usize = 4;
+const MAX_DATA_LEN: usize = 0x80 - 4;
+
+struct Queue {
+    // (operation, data, data length)
+    messages: [(LedOp, [u8; MAX_DATA_LEN], usize); QUEUE_SIZE],
+    first: usize,
+    len: usize,
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            messages: [(LedOp::Reserved, [0; MAX_DATA_LEN], 0); QUEUE_SIZE],
+            first: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, operation: LedOp, data: &[u8]) -> nb::Result<(), !> {
+        if self.len == QUEUE_SIZE || data.len() > MAX_DATA_LEN {
+            return Err(nb::Error::WouldBlock);
+        }
+        let message = &mut self.messages[(self.first + self.len) % QUEUE_SIZE];
+        message.0 = operation;
+        message.1[..data.len()].copy_from_slice(data);
+        message.2 = data.len();
+        self.len += 1;
+        Ok(())
+    }
+
+    fn front(&self) -> Option<(LedOp, &[u8])> {
+        if self.len == 0 {
+            return None;
+        }
+        let (operation, ref data, len) = self.messages[self.first];
+        Some((operation, &data[..len]))
+    }
+
+    fn pop(&mut self) {
+        self.first = (self.first + 1) % QUEUE_SIZE;
+        self.len -= 1;
+    }
+}
+
+// Scans to wait for an AckSetIndividualKeys before all keys are sent again,
+// and how often that's tried before the LED controller counts as failed. The
+// acknowledgement's data is the 0xca marker of the message.
+const ACK_TIMEOUT: u32 = 100;
+const ACK_RETRIES: u8 = 3;
+const SET_KEYS_ACK: u8 = 0xca;
+
+// Lit up in red while gaming mode is on.
+const GAMING_KEYS: [KeyIndex; 4] = [KeyIndex::W, KeyIndex::A, KeyIndex::S, KeyIndex::D];
+
+/// Acknowledgements the LED controller still owes for SetIndividualKeys.
+struct KeyAcks {
+    pending: u8,
+    // scans since the latest message or acknowledgement
+    scans: u32,
+    retries: u8,
+    failed: bool,
+}
+
+impl KeyAcks {
+    fn new() -> KeyAcks {
+        KeyAcks {
+            pending: 0,
+            scans: 0,
+            retries: 0,
+            failed: false,
+        }
+    }
+
+    fn sent(&mut self) {
+        self.pending = self.pending.saturating_add(1);
+        self.scans = 0;
+    }
+
+    fn acknowledged(&mut self) {
+        self.pending = self.pending.saturating_sub(1);
+        self.scans = 0;
+        self.retries = 0;
+        self.failed = false;
+    }
+
+    /// Counts a scan, returns whether the keys have to be sent again.
+    fn tick(&mut self) -> bool {
+        if self.pending == 0 || self.failed {
+            return false;
+        }
+        self.scans += 1;
+        if self.scans < ACK_TIMEOUT {
+            return false;
+        }
+        self.pending = 0;
+        self.scans = 0;
+        if self.retries == ACK_RETRIES {
+            self.failed = true;
+            return false;
+        }
+        self.retries += 1;
+        true
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum LedMode {
     _Off,
     On,
     Flash,
 }
 
+/// The LED controller's settings as it last reported them, None until it
+/// did.
+#[derive(Copy, Clone)]
+pub struct LedConfig {
+    pub theme: Option<u8>,
+    pub brightness: Option<u8>,
+    pub animation_speed: Option<u8>,
+}
+
 pub struct Led<BUFFER: 'static + Unsize<[u8]>> {
     pub serial: Serial<LedUsart, BUFFER>,
     pub rx_transfer: Option<Transfer<BUFFER>>,
     pub pc15: PC15<Output>,
+    /// Whether the LEDs are on, kept in sync with the theme the LED
+    /// controller reports, 0 means off.
     pub state: bool,
+    privacy: bool,
+    // GAMING_KEYS stay lit whenever the theme comes back
+    gaming: bool,
+    framebuffer: Framebuffer,
+    animator: Animator,
+    splash: Splash,
+    test_pattern: TestPattern,
+    // scans left until the battery level goes away
+    battery_scans: u32,
+    upload: Option<ThemeUpload>,
+    // when the framebuffer's fades move on by the next frame
+    next_transition: u32,
+    // the LEDs are off for lack of key presses since last_activity
+    idle: bool,
+    last_activity: u32,
+    config: LedConfig,
+    // brightness and animation speed to jump to once the current ones are known
+    pending_config: (Option<u8>, Option<u8>),
+    queue: Queue,
+    key_acks: KeyAcks,
+    // pressed keys not sent yet, the ones sent last and when the next may go
+    pending_keys: Option<[u8; 9]>,
+    sent_keys: [u8; 9],
+    next_keys: u32,
+    // the keys from before an overlay
+    shadow: Option<Snapshot>,
+    // themes 0 (off) up to the highest one the LED controller reported
+    theme_count: u8,
+    // the answer to the latest GetThemeId
+    theme_id: Option<u8>,
+    random: u32,
 }
 
 impl<BUFFER> Led<BUFFER>
@@ -34,14 +197,50 @@ where
         pc15: PC15<Input>,
     ) -> Led<BUFFER> {
         let rx_transfer = serial.receive(rx_buffer);
+        let mut framebuffer = Framebuffer::new();
+        for &(key, level) in KEY_BRIGHTNESS {
+            framebuffer.set_key_brightness(key, level);
+        }
         Led {
             serial,
             rx_transfer: Some(rx_transfer),
             pc15: pc15.into_output().pull_up(),
             state: false,
+            privacy: false,
+            gaming: false,
+            framebuffer,
+            animator: Animator::new(),
+            splash: Splash::new(),
+            test_pattern: TestPattern::new(),
+            battery_scans: 0,
+            upload: None,
+            next_transition: 0,
+            idle: false,
+            last_activity: 0,
+            config: LedConfig {
+                theme: None,
+                brightness: None,
+                animation_speed: None,
+            },
+            pending_config: (None, None),
+            queue: Queue::new(),
+            key_acks: KeyAcks::new(),
+            pending_keys: None,
+            sent_keys: [0; 9],
+            next_keys: 0,
+            shadow: None,
+            theme_count: 0,
+            theme_id: None,
+            random: 1,
         }
     }
 
+    /// The theme, brightness and animation speed from the LED controller's
+    /// latest acknowledgements.
+    pub fn config(&self) -> LedConfig {
+        self.config
+    }
+
     pub fn on(&mut self) -> nb::Result<(), !> {
         self.pc15.set_high();
         Ok(())
@@ -52,6 +251,32 @@ where
         Ok(())
     }
 
+    pub fn light_on(&mut self) -> nb::Result<(), !> {
+        self.state = true;
+        self.theme_mode()
+    }
+
+    pub fn light_off(&mut self) -> nb::Result<(), !> {
+        self.state = false;
+        self.animator.animation = Animation::Off;
+        self.set_theme(0)
+    }
+
+    /// Blanks the LEDs and ignores everything else until privacy mode is
+    /// switched off again, which brings the theme back if the LEDs were on.
+    pub fn privacy_mode(&mut self, on: bool) -> nb::Result<(), !> {
+        self.privacy = false;
+        let result = if on {
+            self.set_theme(0)
+        } else if self.state {
+            self.theme_mode()
+        } else {
+            Ok(())
+        };
+        self.privacy = on;
+        result
+    }
+
     pub fn toggle(&mut self) -> nb::Result<(), !> {
         let result = if !self.state {
             self.theme_mode()
@@ -64,71 +289,523 @@ where
 
     // next_* cycles through themes/brightness/speed
     pub fn next_theme(&mut self) -> nb::Result<(), !> {
-        self.serial
-            .send(MsgType::Led, LedOp::ConfigCmd as u8, &[1, 0, 0])
+        self.send(LedOp::ConfigCmd, &[1, 0, 0])
+    }
+
+    /// Switches to a random theme other than the current one. The number of
+    /// themes is learned from the LED controller's acknowledgements, until a
+    /// few were seen this works like next_theme().
+    pub fn random_theme(&mut self) -> nb::Result<(), !> {
+        let current = self.config.theme.unwrap_or(0);
+        // themes to pick from, 0 is off
+        let choices = if current == 0 {
+            self.theme_count.saturating_sub(1)
+        } else {
+            self.theme_count.saturating_sub(2)
+        };
+        if choices < 2 {
+            return self.next_theme();
+        }
+
+        // the time of the latest key press is as good as random
+        let mut x = (self.random ^ self.last_activity) | 1;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+
+        let mut theme = 1 + (x % u32::from(choices)) as u8;
+        if current != 0 && theme >= current {
+            theme += 1;
+        }
+        self.set_theme(theme)
     }
 
     pub fn next_brightness(&mut self) -> nb::Result<(), !> {
-        self.serial
-            .send(MsgType::Led, LedOp::ConfigCmd as u8, &[0, 0, 1])
+        self.send(LedOp::ConfigCmd, &[0, 0, 1])
+    }
+
+    // the LED controller adds the bytes to its settings, so 0xff steps back
+    pub fn previous_brightness(&mut self) -> nb::Result<(), !> {
+        self.send(LedOp::ConfigCmd, &[0, 0, 0xff])
     }
 
     pub fn next_animation_speed(&mut self) -> nb::Result<(), !> {
-        self.serial
-            .send(MsgType::Led, LedOp::ConfigCmd as u8, &[0, 1, 0])
+        self.send(LedOp::ConfigCmd, &[0, 1, 0])
+    }
+
+    /// Jumps straight to brightness `level`. ConfigCmd only adds to the
+    /// settings, so if they aren't known yet they're asked for first.
+    pub fn set_brightness(&mut self, level: u8) -> nb::Result<(), !> {
+        self.pending_config.0 = Some(level);
+        self.apply_config()
+    }
+
+    /// Jumps straight to animation speed `level`, like set_brightness().
+    pub fn set_speed(&mut self, level: u8) -> nb::Result<(), !> {
+        self.pending_config.1 = Some(level);
+        self.apply_config()
+    }
+
+    fn apply_config(&mut self) -> nb::Result<(), !> {
+        let (brightness, speed) = self.pending_config;
+        if brightness.is_none() && speed.is_none() {
+            return Ok(());
+        }
+        match (self.config.brightness, self.config.animation_speed) {
+            (Some(current_brightness), Some(current_speed)) => {
+                let brightness = brightness.map_or(0, |b| b.wrapping_sub(current_brightness));
+                let speed = speed.map_or(0, |s| s.wrapping_sub(current_speed));
+                self.send(LedOp::ConfigCmd, &[0, speed, brightness])?;
+                self.pending_config = (None, None);
+                Ok(())
+            }
+            // adding nothing gets the current settings acknowledged
+            _ => self.send(LedOp::ConfigCmd, &[0, 0, 0]),
+        }
     }
 
     pub fn set_theme(&mut self, theme: u8) -> nb::Result<(), !> {
-        self.serial
-            .send(MsgType::Led, LedOp::ThemeMode as u8, &[theme])
+        self.framebuffer.clear();
+        self.send(LedOp::ThemeMode, &[theme])
+    }
+
+    /// Passes the pressed keys on for the LED controller's reactive themes.
+    /// They're sent by animate() at most every KEY_STATE_INTERVAL scans and
+    /// only if they changed, the latest state wins.
+    pub fn send_keys(&mut self, state: &KeyState) {
+        self.pending_keys = Some(to_packed_bits(state).bytes);
     }
 
-    pub fn send_keys(&mut self, state: &KeyState) -> nb::Result<(), !> {
-        let packed = to_packed_bits(state);
-        self.serial
-            .send(MsgType::Led, LedOp::Key as u8, &packed.bytes)
+    fn send_pending_keys(&mut self, now: u32) {
+        let keys = match self.pending_keys {
+            Some(keys) => keys,
+            None => return,
+        };
+        if (now.wrapping_sub(self.next_keys) as i32) < 0 {
+            return;
+        }
+        if keys != self.sent_keys && self.send(LedOp::Key, &keys).is_err() {
+            return;
+        }
+        self.pending_keys = None;
+        self.sent_keys = keys;
+        self.next_keys = now.wrapping_add(KEY_STATE_INTERVAL);
     }
 
     pub fn send_music(&mut self, keys: &[u8]) -> nb::Result<(), !> {
-        self.serial.send(MsgType::Led, LedOp::Music as u8, keys)
+        self.send(LedOp::Music, keys)
     }
 
+    /// Asks the LED controller for its current theme, theme_id() has the
+    /// answer once it's there.
     pub fn get_theme_id(&mut self) -> nb::Result<(), !> {
+        self.theme_id = None;
         // responds with with [ThemeId]
-        self.serial.send(MsgType::Led, LedOp::GetThemeId as u8, &[])
+        self.send(LedOp::GetThemeId, &[])
+    }
+
+    /// The answer to the latest get_theme_id(), WouldBlock until it arrived.
+    pub fn theme_id(&self) -> nb::Result<u8, !> {
+        self.theme_id.ok_or(nb::Error::WouldBlock)
     }
 
-    pub fn set_keys(&mut self, payload: &[u8]) -> nb::Result<(), !> {
-        self.serial
-            .send(MsgType::Led, LedOp::SetIndividualKeys as u8, payload)
+    pub fn set_keys(&mut self, frame: &KeyColorFrame) -> nb::Result<(), !> {
+        self.send(LedOp::SetIndividualKeys, frame.as_bytes())?;
+        if !self.privacy && !self.idle {
+            self.key_acks.sent();
+        }
+        Ok(())
+    }
+
+    /// Whether the LED controller stopped acknowledging key colors, even
+    /// after they were sent again ACK_RETRIES times. The next acknowledgement
+    /// clears it.
+    pub fn failed(&self) -> bool {
+        self.key_acks.failed
     }
 
     pub fn theme_mode(&mut self) -> nb::Result<(), !> {
-        self.serial.send(MsgType::Led, LedOp::ThemeMode as u8, &[])
+        self.framebuffer.clear();
+        if self.gaming {
+            // sent by the next flush()
+            self.show_gaming_keys();
+        }
+        self.send(LedOp::ThemeMode, &[])
+    }
+
+    /// Brings the theme back on keys the framebuffer forgot, the other set
+    /// keys keep their colors. The theme only comes back for all keys at
+    /// once, so they're set again right after.
+    pub fn refresh_theme(&mut self) -> nb::Result<(), !> {
+        let keys = self.framebuffer.snapshot();
+        self.theme_mode()?;
+        self.framebuffer.restore(&keys);
+        self.flush()
+    }
+
+    /// Per-key colors, sent by flush().
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    /// Sets how many frames of ANIMATION_INTERVAL scans it takes keys to fade
+    /// to a new color or brightness.
+    pub fn set_transition(&mut self, frames: u8) {
+        self.framebuffer.set_transition(frames);
+    }
+
+    /// Fades the keys set through the framebuffer to `level` (0 - 255) of
+    /// their brightness, the theme keeps the LED controller's brightness.
+    pub fn fade_brightness(&mut self, level: u8) {
+        self.framebuffer.set_brightness(level);
+    }
+
+    /// Picks the gamma curve for all colors sent from now on, keys that are
+    /// already lit keep their colors until they're set again.
+    pub fn set_gamma(&mut self, gamma: Gamma) {
+        self.framebuffer.set_gamma(gamma);
+    }
+
+    /// Stores `frames` as user theme `slot` in the LED controller's flash.
+    /// The theme goes out in blocks over the following flush() calls.
+    pub fn upload_theme(&mut self, slot: u8, frames: &'static [ThemeFrame]) -> nb::Result<(), !> {
+        self.upload = Some(ThemeUpload::new(slot, frames));
+        self.flush()
     }
 
-    pub fn bluetooth_mode(&mut self, mode: BluetoothMode) -> nb::Result<(), !> {
-        let mode_color = match mode {
-            BluetoothMode::Unknown => (0, 0, 0xff),
-            BluetoothMode::Ble => (0, 0xff, 0),
-            BluetoothMode::Legacy => (0xff, 0xff, 0),
+    /// Sends the keys changed in the framebuffer, split into as many messages
+    /// as needed, and the rest of a theme upload. If the send buffer fills up
+    /// the rest is sent by the next call.
+    pub fn flush(&mut self) -> nb::Result<(), !> {
+        self.continue_upload()?;
+        loop {
+            let mut frame = KeyColorFrame::new();
+            let count = self.framebuffer.encode(&mut frame);
+            if frame.is_empty() {
+                return Ok(());
+            }
+            self.set_keys(&frame)?;
+            self.framebuffer.mark_sent(count);
+        }
+    }
+
+    fn continue_upload(&mut self) -> nb::Result<(), !> {
+        let mut payload = [0; MAX_BLOCK_LEN];
+        loop {
+            let len = match self.upload {
+                Some(ref upload) => upload.encode(&mut payload, self.framebuffer.gamma()),
+                None => return Ok(()),
+            };
+            self.send(LedOp::UserStaticTheme, &payload[..len])?;
+            if let Some(ref mut upload) = self.upload {
+                upload.mark_sent();
+                if !upload.done() {
+                    continue;
+                }
+            }
+            self.upload = None;
+        }
+    }
+
+    /// Switches to the next animation, the theme comes back after the last.
+    pub fn next_animation(&mut self) -> nb::Result<(), !> {
+        let next = self.animator.animation.next();
+        self.set_animation(next)
+    }
+
+    pub fn set_animation(&mut self, animation: Animation) -> nb::Result<(), !> {
+        self.animator.animation = animation;
+        if animation == Animation::Off {
+            return self.theme_mode();
+        }
+        Ok(())
+    }
+
+    /// Lets the animations react to a key press at `now`, and brings the
+    /// LEDs back if they went off while idle.
+    pub fn key_pressed(&mut self, key: usize, now: u32) {
+        self.last_activity = now;
+        if self.idle {
+            self.idle = false;
+            self.theme_mode().log_error();
+        }
+        self.animator.key_pressed(key);
+    }
+
+    /// Renders the current animation into the framebuffer when a frame is
+    /// due, it's sent by the next flush().
+    pub fn animate(&mut self, now: u32) {
+        self.send_pending_keys(now);
+        self.check_idle(now);
+        if self.idle {
+            return;
+        }
+        if self.key_acks.tick() {
+            // sent by the next flush()
+            self.framebuffer.redraw();
+        }
+        if (now.wrapping_sub(self.next_transition) as i32) >= 0 {
+            self.next_transition = now.wrapping_add(ANIMATION_INTERVAL);
+            self.framebuffer.tick();
+        }
+        if self.splash.running() {
+            if !self.splash.tick(now, &mut self.framebuffer) {
+                self.theme_mode().log_error();
+            }
+            return;
+        }
+        if self.test_pattern.running() {
+            if !self.test_pattern.tick(now, &mut self.framebuffer) {
+                self.theme_mode().log_error();
+            }
+            return;
+        }
+        if self.battery_scans > 0 {
+            self.battery_scans -= 1;
+            if self.battery_scans == 0 {
+                self.theme_mode().log_error();
+            }
+            return;
+        }
+        self.animator.tick(now, &mut self.framebuffer);
+    }
+
+    /// Lights up one key of the number row for every 10%, from red when
+    /// empty to green when full, for BATTERY_TIMEOUT scans.
+    pub fn show_battery(&mut self, percent: u8) -> nb::Result<(), !> {
+        self.battery_scans = BATTERY_TIMEOUT;
+        let lit = (percent.min(100) as usize + 9) / 10;
+        self.number_row_bar(lit, false)
+    }
+
+    /// Darkens all keys and lights them up one after the other in red,
+    /// green, blue and white, then the theme comes back.
+    pub fn test_pattern(&mut self) -> nb::Result<(), !> {
+        let off = Rgb(0, 0, 0);
+        for index in 0..KEYS {
+            self.framebuffer.set_index_now(index, off, LedMode::On);
+        }
+        self.test_pattern.start();
+        self.flush()
+    }
+
+    /// Lights up one key of the number row for every WPM_PER_KEY words per
+    /// minute, from green when slow to red when fast.
+    pub fn show_wpm(&mut self, wpm: u32) -> nb::Result<(), !> {
+        let lit = ((wpm + WPM_PER_KEY - 1) / WPM_PER_KEY).min(10) as usize;
+        self.number_row_bar(lit, true)
+    }
+
+    /// Colors every key by how often it was pressed, from blue for the least
+    /// used keys to red for the most used.
+    pub fn show_heatmap(&mut self, stats: &TypingStats) -> nb::Result<(), !> {
+        for key in 0..KEYS {
+            let heat = stats.heat(key);
+            let color = Rgb(heat, 0, 255 - heat);
+            self.framebuffer.set_index(key, color, LedMode::On);
+        }
+        self.flush()
+    }
+
+    /// Shows `time` as a binary clock on 1-6 down to Z-N: a column for each
+    /// digit of the hours (red), minutes (green) and seconds (blue), with the
+    /// lowest bit on the bottom row.
+    pub fn show_clock(&mut self, time: Time) -> nb::Result<(), !> {
+        let (h, m, s) = (time.hours, time.minutes, time.seconds);
+        let digits = [h / 10, h % 10, m / 10, m % 10, s / 10, s % 10];
+        let colors = [Rgb(0xff, 0, 0), Rgb(0, 0xff, 0), Rgb(0, 0, 0xff)];
+        let off = Rgb(0, 0, 0);
+
+        for (column, digit) in digits.iter().enumerate() {
+            for bit in 0..4 {
+                let lit = digit & (1 << bit) != 0;
+                let color = if lit { colors[column / 2] } else { off };
+                let index = (3 - bit) * COLUMNS + 1 + column;
+                self.framebuffer.set_index(index, color, LedMode::On);
+            }
+        }
+        self.flush()
+    }
+
+    // Lights up the first `lit` keys of the number row, in green when all of
+    // them are lit down to red when none are, or the other way around
+    fn number_row_bar(&mut self, lit: usize, reversed: bool) -> nb::Result<(), !> {
+        let level = (lit * 255 / 10) as u8;
+        let level = if reversed { 255 - level } else { level };
+        let color = Rgb(255 - level, level, 0);
+
+        let first = KeyIndex::N1 as usize;
+        for i in 0..10 {
+            let color = if i < lit { color } else { Rgb(0, 0, 0) };
+            self.framebuffer.set_index(first + i, color, LedMode::On);
+        }
+        self.flush()
+    }
+
+    /// Remembers the set keys before an overlay like the BT layer's status
+    /// covers them.
+    pub fn begin_overlay(&mut self) {
+        if self.shadow.is_none() {
+            self.shadow = Some(self.framebuffer.snapshot());
+        }
+    }
+
+    /// Drops the overlay: the theme comes back, and the keys that were set
+    /// before begin_overlay() get their colors back.
+    pub fn end_overlay(&mut self) -> nb::Result<(), !> {
+        self.theme_mode()?;
+        if let Some(shadow) = self.shadow.take() {
+            self.framebuffer.restore(&shadow);
+        }
+        self.flush()
+    }
+
+    /// Lights up the BT layer: saved hosts on 1-4 and A-F, the connected
+    /// one in green, the slot that's pairing flashing on 1-4 and Q-R, and the
+    /// mode on 0.
+    pub fn bluetooth_mode(&mut self, status: &BluetoothStatus) -> nb::Result<(), !> {
+        let mode_color = match status.mode {
+            BluetoothMode::Unknown => Rgb(0, 0, 0xff),
+            BluetoothMode::Ble => Rgb(0, 0xff, 0),
+            BluetoothMode::Legacy => Rgb(0xff, 0xff, 0),
         };
+        let (red, green, yellow) = (Rgb(0xff, 0, 0), Rgb(0, 0xff, 0), Rgb(0xff, 0xff, 0));
+        let (blue, white, off) = (Rgb(0, 0, 0xff), Rgb(0xff, 0xff, 0xff), Rgb(0, 0, 0));
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        let payload = &[0xca, 0x0a,
-            KeyIndex::Escape as u8, 0xff, 0xff, 0x00, LedMode::On as u8,
-            KeyIndex::N1 as u8,     0xff, 0x00, 0x00, LedMode::Flash as u8,
-            KeyIndex::N2 as u8,     0xff, 0x00, 0x00, LedMode::On as u8,
-            KeyIndex::N3 as u8,     0xff, 0x00, 0x00, LedMode::On as u8,
-            KeyIndex::N4 as u8,     0xff, 0x00, 0x00, LedMode::On as u8,
-            KeyIndex::Equal as u8,  0x00, 0xff, 0x00, LedMode::On as u8,
-            KeyIndex::B as u8,      0x00, 0xff, 0x00, LedMode::Flash as u8,
-            KeyIndex::Minus as u8,     0x00, 0xff, 0x00, LedMode::On as u8,
-            KeyIndex::N0 as u8,  mode_color.0, mode_color.1, mode_color.2, LedMode::On as u8,
-            KeyIndex::A as u8,      0x00, 0xff, 0x00, LedMode::On as u8,
-        ];
+        {
+            let keys = &mut self.framebuffer;
+            keys.set(KeyIndex::Escape, yellow, LedMode::On);
+            keys.set(KeyIndex::B, yellow, LedMode::On);
+            for host in 0..HOSTS {
+                let saved = status.saved_hosts & (1 << host) != 0;
+                let pairing = status.pairing == Pairing::Discoverable(host);
+                let connected = status.connected_host == Some(host);
+                let connecting = status.profile == Some(host) && !connected;
+                let color = if pairing {
+                    blue
+                } else if connected || connecting {
+                    green
+                } else if saved {
+                    white
+                } else {
+                    off
+                };
+                let mode = if pairing || connecting {
+                    LedMode::Flash
+                } else {
+                    LedMode::On
+                };
+                let delete_color = if saved { red } else { off };
 
-        self.set_keys(payload)
+                let host = host as usize;
+                keys.set_index(KeyIndex::N1 as usize + host, color, mode);
+                keys.set_index(KeyIndex::Q as usize + host, blue, mode);
+                keys.set_index(KeyIndex::A as usize + host, delete_color, LedMode::On);
+            }
+            keys.set(KeyIndex::N0, mode_color, LedMode::On);
+            keys.set(KeyIndex::Minus, red, LedMode::On);
+            keys.set(KeyIndex::Equal, blue, LedMode::On);
+            keys.set(KeyIndex::BSpace, green, LedMode::On);
+        }
+        self.flush()
+    }
+
+    /// Sets all keys of matrix row `row`, from 0 for the number row to 4 for
+    /// the bottom row. flush() sends them.
+    pub fn set_row(&mut self, row: usize, color: Rgb, mode: LedMode) {
+        for column in 0..COLUMNS {
+            self.set_position(row, column, color, mode);
+        }
+    }
+
+    /// Sets all keys of matrix column `column`, from 0 for the Esc column to
+    /// 13 for the Backspace column. flush() sends them.
+    pub fn set_column(&mut self, column: usize, color: Rgb, mode: LedMode) {
+        for row in 0..ROWS {
+            self.set_position(row, column, color, mode);
+        }
+    }
+
+    // Sets the key at `row` and `column`, if there's one
+    fn set_position(&mut self, row: usize, column: usize, color: Rgb, mode: LedMode) {
+        let index = row * COLUMNS + column;
+        let no_key = NO_KEYS.iter().any(|key| *key as usize == index);
+        if row < ROWS && column < COLUMNS && !no_key {
+            self.framebuffer.set_index(index, color, mode);
+        }
+    }
+
+    /// Lights up W/A/S/D in red while gaming mode is on. They're drawn again
+    /// every time the theme comes back, so other overlays don't wipe them.
+    pub fn gaming_mode(&mut self, on: bool) -> nb::Result<(), !> {
+        self.gaming = on;
+        if !on {
+            for key in &GAMING_KEYS {
+                self.framebuffer.forget(*key as usize);
+            }
+            return self.refresh_theme();
+        }
+
+        self.show_gaming_keys();
+        self.flush()
+    }
+
+    fn show_gaming_keys(&mut self) {
+        for key in &GAMING_KEYS {
+            self.framebuffer.set(*key, Rgb(0xff, 0, 0), LedMode::On);
+        }
+    }
+
+    // Switches the LEDs off once they were on without key presses for
+    // LED_IDLE_TIMEOUT scans, nothing is sent until the next key press.
+    fn check_idle(&mut self, now: u32) {
+        if LED_IDLE_TIMEOUT == 0 || self.idle || !self.state {
+            return;
+        }
+        if now.wrapping_sub(self.last_activity) >= LED_IDLE_TIMEOUT {
+            self.set_theme(0).log_error();
+            self.idle = true;
+        }
+    }
+
+    // Counts the themes up to `theme`. Theme 0 means the LEDs are off, unless
+    // they were only blanked for privacy or idle mode
+    fn theme_reported(&mut self, theme: u8) {
+        self.config.theme = Some(theme);
+        self.theme_count = self.theme_count.max(theme.saturating_add(1));
+        if !self.privacy && !self.idle {
+            self.state = theme != 0;
+        }
+    }
+
+    /// Sends or queues a message, it only fails once the queue is full.
+    fn send(&mut self, operation: LedOp, data: &[u8]) -> nb::Result<(), !> {
+        if self.privacy || self.idle {
+            return Ok(());
+        }
+        self.send_queued();
+        if self.queue.len > 0 {
+            return self.queue.push(operation, data);
+        }
+        match self.serial.send(MsgType::Led, operation as u8, data) {
+            Err(nb::Error::WouldBlock) => self.queue.push(operation, data),
+            result => result,
+        }
+    }
+
+    /// Sends queued messages in order, until the send buffer is busy again.
+    pub fn send_queued(&mut self) {
+        loop {
+            let sent = match self.queue.front() {
+                Some((operation, data)) => self.serial.send(MsgType::Led, operation as u8, data),
+                None => return,
+            };
+            if sent.is_err() {
+                return;
+            }
+            self.queue.pop();
+        }
     }
 
     pub fn handle_message(&mut self, message: &Message) {
@@ -138,13 +815,32 @@ where
                     LedOp::AckThemeMode => {
                         // data: [theme id]
                         //debug!("Led AckThemeMode {:?}", message.data).ok();
+                        if let Some(&theme) = message.data.first() {
+                            self.theme_reported(theme);
+                        }
+                    }
+                    LedOp::AckGetThemeId => {
+                        // data: [theme id]
+                        if let Some(&theme) = message.data.first() {
+                            self.theme_id = Some(theme);
+                            self.theme_reported(theme);
+                        }
                     }
                     LedOp::AckConfigCmd => {
                         // data: [theme id, brightness, animation speed]
                         //debug!("Led AckConfigCmd {:?}", message.data).ok();
+                        if message.data.len() == 3 {
+                            self.config.brightness = Some(message.data[1]);
+                            self.config.animation_speed = Some(message.data[2]);
+                            self.theme_reported(message.data[0]);
+                            self.apply_config().log_error();
+                        }
                     }
                     LedOp::AckSetIndividualKeys => {
                         // data: [202]
+                        if message.data.first() == Some(&SET_KEYS_ACK) {
+                            self.key_acks.acknowledged();
+                        }
                     }
                     _ => {
                         debug!(
@@ -195,4 +891,5 @@ pub fn rx(_t: &mut Threshold, mut r: super::DMA1_CHANNEL3::Resources) {
 
 pub fn tx(_t: &mut Threshold, mut r: super::DMA1_CHANNEL2::Resources) {
     r.LED.serial.tx_interrupt();
+    r.LED.send_queued();
 }
@@ -0,0 +1,180 @@
+use action::Action;
+use keycodes::KeyCode;
+use layout::MACROS;
+
+/// Crate version and git commit of the build, see build.rs.
+pub const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " ", env!("GIT_HASH"));
+
+/// One step of a macro, see layout::MACROS.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum MacroStep {
+    /// press and release a key
+    Tap(KeyCode),
+    /// keep a key pressed until a Release step or the end of the macro
+    Press(KeyCode),
+    Release(KeyCode),
+    /// tap a key while holding a modifier
+    Modified(KeyCode, KeyCode),
+    /// wait for a number of scans
+    Wait(u32),
+}
+
+impl MacroStep {
+    // Key press or release at `phase` of this step, one per scan
+    fn event(&self, phase: u8) -> Option<(KeyCode, bool)> {
+        match (*self, phase) {
+            (MacroStep::Tap(key), 0) | (MacroStep::Press(key), 0) => Some((key, true)),
+            (MacroStep::Tap(key), 1) | (MacroStep::Release(key), 0) => Some((key, false)),
+            (MacroStep::Modified(modifier, _), 0) => Some((modifier, true)),
+            (MacroStep::Modified(_, key), 1) => Some((key, true)),
+            (MacroStep::Modified(_, key), 2) => Some((key, false)),
+            (MacroStep::Modified(modifier, _), 3) => Some((modifier, false)),
+            _ => None,
+        }
+    }
+}
+
+const MAX_HELD_KEYS: usize = 4;
+
+#[derive(Copy, Clone)]
+enum Playing {
+    Macro(usize),
+    Text(&'static str),
+}
+
+/// Plays back macros from layout::MACROS and text one key event per scan.
+pub struct MacroPlayer {
+    playing: Option<Playing>,
+    step: usize,
+    phase: u8,
+    // end of the current Wait step
+    until: u32,
+    // keys pressed by the macro, KeyCode::No marks a free slot
+    held: [KeyCode; MAX_HELD_KEYS],
+    last: Option<(Action, bool, bool)>,
+}
+
+impl MacroPlayer {
+    pub const fn new() -> MacroPlayer {
+        MacroPlayer {
+            playing: None,
+            step: 0,
+            phase: 0,
+            until: 0,
+            held: [KeyCode::No; MAX_HELD_KEYS],
+            last: None,
+        }
+    }
+
+    pub fn play(&mut self, id: u8) {
+        if (id as usize) < MACROS.len() {
+            self.start(Playing::Macro(id as usize));
+        }
+    }
+
+    /// Types `text`, which may only contain lowercase letters, digits, spaces,
+    /// dots and dashes.
+    pub fn type_text(&mut self, text: &'static str) {
+        self.start(Playing::Text(text));
+    }
+
+    fn start(&mut self, playing: Playing) {
+        self.playing = Some(playing);
+        self.step = 0;
+        self.phase = 0;
+    }
+
+    fn current_step(&self) -> Option<MacroStep> {
+        match self.playing {
+            Some(Playing::Macro(id)) => MACROS[id].get(self.step).cloned(),
+            Some(Playing::Text(text)) => text
+                .as_bytes()
+                .get(self.step)
+                .map(|c| MacroStep::Tap(key_for(*c))),
+            None => None,
+        }
+    }
+
+    /// Keys the macro keeps pressed, they have to be added to every report.
+    pub fn held(&self) -> &[KeyCode] {
+        &self.held
+    }
+
+    fn hold(&mut self, key: KeyCode, pressed: bool) {
+        if !pressed {
+            for slot in self.held.iter_mut().filter(|held| **held == key) {
+                *slot = KeyCode::No;
+            }
+        } else if !self.held.contains(&key) {
+            if let Some(slot) = self.held.iter_mut().find(|held| **held == KeyCode::No) {
+                *slot = key;
+            }
+        }
+    }
+
+    /// Returns the (action, pressed, changed) event for this scan.
+    ///
+    /// Playback only moves on once the previous report has been sent,
+    /// otherwise the last event is repeated.
+    pub fn poll(&mut self, now: u32, report_sent: bool) -> Option<(Action, bool, bool)> {
+        if !report_sent && self.last.is_some() {
+            return self.last;
+        }
+
+        let step = match self.current_step() {
+            Some(step) => step,
+            None => {
+                // keys left pressed are released with the end of the macro
+                self.held = [KeyCode::No; MAX_HELD_KEYS];
+                self.playing = None;
+                self.last = None;
+                return None;
+            }
+        };
+
+        let event = match step.event(self.phase) {
+            Some((key, pressed)) => {
+                self.phase += 1;
+                self.hold(key, pressed);
+                Some((Action::Key(key), pressed, true))
+            }
+            None => None,
+        };
+        let done = match step {
+            MacroStep::Wait(scans) => {
+                if self.phase == 0 {
+                    self.until = now.wrapping_add(scans);
+                    self.phase = 1;
+                }
+                (now.wrapping_sub(self.until) as i32) >= 0
+            }
+            _ => step.event(self.phase).is_none(),
+        };
+        if done {
+            self.step += 1;
+            self.phase = 0;
+        }
+
+        self.last = event;
+        event
+    }
+}
+
+// Key that types the ASCII character `c` without Shift
+fn key_for(c: u8) -> KeyCode {
+    let code = if c >= b'a' && c <= b'z' {
+        KeyCode::A as u8 + (c - b'a')
+    } else if c >= b'1' && c <= b'9' {
+        KeyCode::N1 as u8 + (c - b'1')
+    } else {
+        return match c {
+            b'0' => KeyCode::N0,
+            b' ' => KeyCode::Space,
+            b'.' => KeyCode::Dot,
+            b'-' => KeyCode::Minus,
+            _ => KeyCode::No,
+        };
+    };
+    KeyCode::from_u8(code).unwrap_or(KeyCode::No)
+}
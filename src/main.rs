@@ -19,28 +19,51 @@ mod debug;
 
 #[macro_use]
 mod action;
+mod animation;
 mod bluetooth;
+mod bootmagic;
 mod clock;
+mod color;
+mod combo;
+mod compose;
+mod dynamic_macro;
+mod framebuffer;
+mod gamma;
 mod hidreport;
+mod key_override;
 mod keyboard;
 mod keycodes;
+mod keymap;
 mod keymatrix;
 mod layout;
+mod leader;
 mod led;
+mod macros;
 mod protocol;
+mod rtc;
 mod serial;
+mod settings;
+mod stats;
+mod steno;
+mod tapdance;
+mod theme;
+mod unicode;
 
 use hal::dma::DmaExt;
 use hal::gpio::GpioExt;
 use rtfm::{app, Threshold};
 
 use bluetooth::Bluetooth;
+use debug::UnwrapLog;
 use keyboard::Keyboard;
 use keymatrix::KeyMatrix;
+use layout::USER_THEME;
 use led::Led;
+use rtc::Rtc;
 use serial::Serial;
 use serial::bluetooth_usart::BluetoothUsart;
 use serial::led_usart::LedUsart;
+use settings::Settings;
 
 app! {
     device: stm32l151,
@@ -53,18 +76,20 @@ app! {
         static BLUETOOTH: Bluetooth<[u8; 0x80]>;
         static LED_BUFFERS: [[u8; 0x80]; 2] = [[0; 0x80]; 2];
         static LED: Led<[u8; 0x80]>;
+        static SETTINGS: Settings;
+        static RTC: Rtc;
         static SYST: stm32l151::SYST;
         static EXTI: stm32l151::EXTI;
     },
 
     init: {
-        resources: [BLUETOOTH_BUFFERS, LED_BUFFERS],
+        resources: [BLUETOOTH_BUFFERS, LED_BUFFERS, KEYBOARD],
     },
 
     tasks: {
         SYS_TICK: {
             path: tick,
-            resources: [BLUETOOTH, LED, KEY_MATRIX, SYST, KEYBOARD],
+            resources: [BLUETOOTH, LED, KEY_MATRIX, SYST, KEYBOARD, RTC, SETTINGS],
         },
         DMA1_CHANNEL2: {
             path: led::tx,
@@ -117,6 +142,9 @@ fn init(mut p: init::Peripherals, r: init::Resources) -> init::LateResources {
     clock::init_clock(&d);
     clock::enable_tick(&mut p.core.SYST, 100_000);
 
+    let mut settings = Settings::new(d.FLASH);
+    let rtc = Rtc::new(d.RTC, &d.PWR, &mut d.RCC);
+
     let dma = d.DMA1.split();
     let gpioa = d.GPIOA.split();
     let gpiob = d.GPIOB.split();
@@ -148,13 +176,22 @@ fn init(mut p: init::Peripherals, r: init::Resources) -> init::LateResources {
         gpiob.pb5.into_output().pull_up(),
     );
 
-    let key_matrix = KeyMatrix::new(row_pins, column_pins);
+    let mut key_matrix = KeyMatrix::new(row_pins, column_pins);
+    // scan once to check for keys held down at power-up
+    key_matrix.sample(&p.core.SYST);
+    bootmagic::check(&key_matrix, &p.core.SCB, &mut settings);
+    r.KEYBOARD.load_settings(&settings);
 
     let led_usart = LedUsart::new(d.USART3, gpiob.pb10, gpiob.pb11, dma.3, dma.2, &mut d.RCC);
     let (led_send_buffer, led_receive_buffer) = r.LED_BUFFERS.split_at_mut(1);
     let led_serial = Serial::new(led_usart, &mut led_send_buffer[0]);
     let mut led = Led::new(led_serial, &mut led_receive_buffer[0], gpioc.pc15);
     led.on().unwrap();
+    // the LED controller may have kept its lights on across a reset
+    led.get_theme_id().log_error();
+    if let Some((slot, frames)) = USER_THEME {
+        led.upload_theme(slot, frames).log_error();
+    }
 
     let bluetooth_usart = BluetoothUsart::new(
         d.USART2,
@@ -173,6 +210,8 @@ fn init(mut p: init::Peripherals, r: init::Resources) -> init::LateResources {
         BLUETOOTH: bluetooth,
         KEY_MATRIX: key_matrix,
         LED: led,
+        SETTINGS: settings,
+        RTC: rtc,
         SYST: p.core.SYST,
         EXTI: d.EXTI,
     }
@@ -186,8 +225,9 @@ fn idle() -> ! {
 
 fn tick(_t: &mut Threshold, mut r: SYS_TICK::Resources) {
     r.KEY_MATRIX.sample(&r.SYST);
+    let (matrix, rtc) = (&r.KEY_MATRIX, &r.RTC);
     r.KEYBOARD
-        .process(&r.KEY_MATRIX.state, &mut r.BLUETOOTH, &mut r.LED);
+        .process(matrix, &mut r.BLUETOOTH, &mut r.LED, rtc, &mut r.SETTINGS);
 }
 
 fn exti0(_t: &mut Threshold, r: EXTI0::Resources) {
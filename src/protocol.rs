@@ -130,6 +130,7 @@ pub enum LedOp {
     AckGetUserStaticTheme = 137,
     AckGetUserStaticCrcId = 138,
     AckSetIndividualKeys = 139,
+    AckGetThemeId = 140,
 }
 
 impl From<u8> for LedOp {
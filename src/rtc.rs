@@ -0,0 +1,91 @@
+use stm32l151::{PWR, RCC, RTC};
+
+// The RTC runs off the internal ~37kHz LSI, these prescalers divide it down
+// to 1Hz. The LSI is off by a few percent.
+const PREDIV_A: u8 = 127;
+const PREDIV_S: u16 = 288;
+// Monday 2001-01-01: ISR.INITS only reads set while the year isn't 0, which
+// is how a reset tells the clock was set already.
+const DATE: u32 = 0x0001_2101;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Time {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// The real-time clock, it keeps running across resets as long as the
+/// keyboard has power.
+pub struct Rtc {
+    rtc: RTC,
+}
+
+impl Rtc {
+    pub fn new(rtc: RTC, pwr: &PWR, rcc: &mut RCC) -> Rtc {
+        // the RTC registers are write protected in the backup domain
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        rcc.csr.modify(|_, w| w.lsion().set_bit());
+        while rcc.csr.read().lsirdy().bit_is_clear() {}
+        rcc.csr.modify(|_, w| unsafe { w.rtcsel().bits(0b10) });
+        rcc.csr.modify(|_, w| w.rtcen().set_bit());
+
+        let mut clock = Rtc { rtc };
+        if clock.rtc.isr.read().inits().bit_is_clear() {
+            clock.set_time(Time {
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+            });
+        }
+        clock
+    }
+
+    pub fn time(&self) -> Time {
+        let tr = self.rtc.tr.read();
+        // reading TR locks the shadow registers until DR is read
+        self.rtc.dr.read();
+        Time {
+            hours: (tr.ht().bits() * 10 + tr.hu().bits()) as u8,
+            minutes: (tr.mnt().bits() * 10 + tr.mnu().bits()) as u8,
+            seconds: (tr.st().bits() * 10 + tr.su().bits()) as u8,
+        }
+    }
+
+    pub fn set_time(&mut self, time: Time) {
+        let Time {
+            hours,
+            minutes,
+            seconds,
+        } = time;
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return;
+        }
+
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0xca) });
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0x53) });
+        self.rtc.isr.modify(|_, w| w.init().set_bit());
+        while self.rtc.isr.read().initf().bit_is_clear() {}
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        self.rtc.prer.write(|w| unsafe {
+            w.prediv_s().bits(PREDIV_S)
+             .prediv_a().bits(PREDIV_A)
+        });
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        self.rtc.tr.write(|w| unsafe {
+            w.pm().clear_bit()
+             .ht().bits(hours / 10)
+             .hu().bits(hours % 10)
+             .mnt().bits(minutes / 10)
+             .mnu().bits(minutes % 10)
+             .st().bits(seconds / 10)
+             .su().bits(seconds % 10)
+        });
+        self.rtc.dr.write(|w| unsafe { w.bits(DATE) });
+
+        self.rtc.isr.modify(|_, w| w.init().clear_bit());
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0xff) });
+    }
+}
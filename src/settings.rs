@@ -0,0 +1,122 @@
+use bluetooth::HOSTS;
+use core::ptr;
+use keymatrix::{COLUMNS, ROWS};
+use stm32l151::FLASH;
+use unicode::UnicodeMode;
+
+// The data EEPROM keeps its contents across power cycles and firmware updates.
+// Erased words read as 0, so every setting defaults to 0.
+const EEPROM: usize = 0x0808_0000;
+const PEKEY1: u32 = 0x89AB_CDEF;
+const PEKEY2: u32 = 0x0203_0405;
+
+// Word offsets into the EEPROM
+const DEFAULT_LAYER: usize = 0;
+const ACTIVE_KEYMAP: usize = 1;
+// press counts of the typing heatmap, two keys per word
+const KEY_PRESSES: usize = ACTIVE_KEYMAP + 1;
+const KEY_PRESSES_SIZE: usize = (ROWS * COLUMNS + 1) / 2;
+// keymap and Unicode mode of each Bluetooth host, one word each
+const HOST_SETTINGS: usize = KEY_PRESSES + KEY_PRESSES_SIZE;
+const HOST_SETTINGS_SIZE: usize = HOSTS as usize;
+// Number of words in use
+const SIZE: usize = HOST_SETTINGS + HOST_SETTINGS_SIZE;
+
+// Host settings words: the set bit, the keymap and the Unicode mode
+const HOST_SET: u32 = 1 << 31;
+
+/// Settings that survive power cycles, stored in the data EEPROM.
+pub struct Settings {
+    flash: FLASH,
+}
+
+impl Settings {
+    pub fn new(flash: FLASH) -> Settings {
+        Settings { flash }
+    }
+
+    pub fn default_layer(&self) -> u8 {
+        self.read(DEFAULT_LAYER) as u8
+    }
+
+    pub fn set_default_layer(&mut self, layer: u8) {
+        self.write(DEFAULT_LAYER, u32::from(layer));
+    }
+
+    pub fn active_keymap(&self) -> u8 {
+        self.read(ACTIVE_KEYMAP) as u8
+    }
+
+    pub fn set_active_keymap(&mut self, id: u8) {
+        self.write(ACTIVE_KEYMAP, u32::from(id));
+    }
+
+    /// How often `key` was pressed, saved for the typing heatmap.
+    pub fn key_presses(&self, key: usize) -> u16 {
+        if key / 2 >= KEY_PRESSES_SIZE {
+            return 0;
+        }
+        (self.read(KEY_PRESSES + key / 2) >> (16 * (key % 2))) as u16
+    }
+
+    pub fn set_key_presses(&mut self, key: usize, count: u16) {
+        if key / 2 >= KEY_PRESSES_SIZE {
+            return;
+        }
+        let shift = 16 * (key % 2);
+        let word = self.read(KEY_PRESSES + key / 2) & !(0xffff << shift);
+        self.write(KEY_PRESSES + key / 2, word | u32::from(count) << shift);
+    }
+
+    /// Keymap and Unicode mode saved for Bluetooth host `host`, None until
+    /// the host was connected once.
+    pub fn host_settings(&self, host: u8) -> Option<(u8, UnicodeMode)> {
+        if host >= HOSTS {
+            return None;
+        }
+        let word = self.read(HOST_SETTINGS + host as usize);
+        if word & HOST_SET == 0 {
+            return None;
+        }
+        let mode = match word as u8 {
+            1 => UnicodeMode::MacOs,
+            2 => UnicodeMode::WinCompose,
+            _ => UnicodeMode::Linux,
+        };
+        Some(((word >> 8) as u8, mode))
+    }
+
+    pub fn set_host_settings(&mut self, host: u8, keymap: u8, mode: UnicodeMode) {
+        if host >= HOSTS {
+            return;
+        }
+        let word = HOST_SET | u32::from(keymap) << 8 | mode as u32;
+        self.write(HOST_SETTINGS + host as usize, word);
+    }
+
+    /// Resets all settings to their defaults.
+    pub fn reset(&mut self) {
+        for offset in 0..SIZE {
+            self.write(offset, 0);
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((EEPROM as *const u32).offset(offset as isize)) }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        // EEPROM wears out, skip writes that don't change anything
+        if self.read(offset) == value {
+            return;
+        }
+
+        self.flash.pekeyr.write(|w| unsafe { w.bits(PEKEY1) });
+        self.flash.pekeyr.write(|w| unsafe { w.bits(PEKEY2) });
+
+        unsafe { ptr::write_volatile((EEPROM as *mut u32).offset(offset as isize), value) };
+        while self.flash.sr.read().bsy().bit_is_set() {}
+
+        self.flash.pecr.modify(|_, w| w.pelock().set_bit());
+    }
+}
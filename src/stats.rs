@@ -0,0 +1,91 @@
+use framebuffer::KEYS;
+use layout::WPM_UPDATE_INTERVAL;
+use settings::Settings;
+
+// seconds of key presses the typing speed is averaged over
+const WPM_WINDOW: usize = 10;
+// key presses per word
+const WORD_LENGTH: u32 = 5;
+
+/// Typing statistics from the key presses of the matrix.
+pub struct TypingStats {
+    /// the typing speed is shown on the number row
+    pub wpm_shown: bool,
+    /// all keys are colored by how often they were pressed
+    pub heatmap_shown: bool,
+    // presses of every key, in KeyIndex order
+    counts: [u16; KEYS],
+    // key presses of the latest seconds, `second` is the current one
+    presses: [u16; WPM_WINDOW],
+    second: usize,
+    next_update: u32,
+}
+
+impl TypingStats {
+    pub const fn new() -> TypingStats {
+        TypingStats {
+            wpm_shown: false,
+            heatmap_shown: false,
+            counts: [0; KEYS],
+            presses: [0; WPM_WINDOW],
+            second: 0,
+            next_update: 0,
+        }
+    }
+
+    pub fn key_pressed(&mut self, key: usize) {
+        let presses = &mut self.presses[self.second];
+        *presses = presses.saturating_add(1);
+
+        if key < KEYS {
+            // halving all counts keeps them relative to each other
+            if self.counts[key] == u16::max_value() {
+                for count in self.counts.iter_mut() {
+                    *count /= 2;
+                }
+            }
+            self.counts[key] += 1;
+        }
+    }
+
+    /// How often `key` was pressed compared to the most pressed key, from 0
+    /// to 255.
+    pub fn heat(&self, key: usize) -> u8 {
+        let max = self.counts.iter().cloned().max().unwrap_or(0);
+        if max == 0 || key >= KEYS {
+            return 0;
+        }
+        (u32::from(self.counts[key]) * 255 / u32::from(max)) as u8
+    }
+
+    pub fn load(&mut self, settings: &Settings) {
+        for (key, count) in self.counts.iter_mut().enumerate() {
+            *count = settings.key_presses(key);
+        }
+    }
+
+    pub fn save(&self, settings: &mut Settings) {
+        for (key, count) in self.counts.iter().enumerate() {
+            settings.set_key_presses(key, *count);
+        }
+    }
+
+    /// Words per minute over the latest WPM_WINDOW seconds.
+    pub fn wpm(&self) -> u32 {
+        let presses: u32 = self.presses.iter().map(|p| u32::from(*p)).sum();
+        presses * 60 / WPM_WINDOW as u32 / WORD_LENGTH
+    }
+
+    /// Starts the next second once WPM_UPDATE_INTERVAL scans passed, returns
+    /// the words per minute at that point.
+    pub fn poll(&mut self, now: u32) -> Option<u32> {
+        if (now.wrapping_sub(self.next_update) as i32) < 0 {
+            return None;
+        }
+        self.next_update = now.wrapping_add(WPM_UPDATE_INTERVAL);
+        let wpm = self.wpm();
+        self.second = (self.second + 1) % WPM_WINDOW;
+        self.presses[self.second] = 0;
+        Some(wpm)
+    }
+}
@@ -0,0 +1,144 @@
+use keycodes::KeyCode;
+
+/// Steno keys in the order of the GeminiPR protocol.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum StenoKey {
+    Fn,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    S1,
+    S2,
+    TL,
+    KL,
+    PL,
+    WL,
+    HL,
+    RL,
+    A,
+    O,
+    Star1,
+    Star2,
+    Res1,
+    Res2,
+    Pwr,
+    Star3,
+    Star4,
+    E,
+    U,
+    FR,
+    RR,
+    PR,
+    BR,
+    LR,
+    GR,
+    TR,
+    SR,
+    DR,
+    Num7,
+    Num8,
+    Num9,
+    NumA,
+    NumB,
+    NumC,
+    ZR,
+}
+
+const STENO_KEYS: usize = 42;
+
+// Keys of Plover's default QWERTY keyboard layout, in StenoKey order
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const KEYS: [KeyCode; STENO_KEYS] = [
+    KeyCode::No, KeyCode::N1, KeyCode::N2, KeyCode::N3, KeyCode::N4, KeyCode::N5, KeyCode::N6,
+    KeyCode::Q, KeyCode::A, KeyCode::W, KeyCode::S, KeyCode::E, KeyCode::D, KeyCode::R,
+    KeyCode::F, KeyCode::C, KeyCode::V, KeyCode::T, KeyCode::Y, KeyCode::No, KeyCode::No,
+    KeyCode::No, KeyCode::G, KeyCode::H, KeyCode::N, KeyCode::M, KeyCode::U, KeyCode::J,
+    KeyCode::I, KeyCode::K, KeyCode::O, KeyCode::L, KeyCode::P, KeyCode::SColon, KeyCode::LBracket,
+    KeyCode::N7, KeyCode::N8, KeyCode::N9, KeyCode::N0, KeyCode::Minus, KeyCode::Equal, KeyCode::Quote,
+];
+
+const GROUP_SIZE: usize = 6;
+
+/// Steno chords: keys are collected while they're held down, and the chord
+/// is typed for Plover's keyboard machine once all of them are released.
+///
+/// The chord is typed in groups of up to six keys, one key of each group
+/// stays held during the next one, so the host never sees all keys released
+/// in the middle of the chord.
+pub struct Steno {
+    chord: u64,
+    held: u8,
+    // keys of the chord that still have to be typed
+    typing: u64,
+    group: [KeyCode; GROUP_SIZE],
+    group_len: usize,
+}
+
+impl Steno {
+    pub const fn new() -> Steno {
+        Steno {
+            chord: 0,
+            held: 0,
+            typing: 0,
+            group: [KeyCode::No; GROUP_SIZE],
+            group_len: 0,
+        }
+    }
+
+    pub fn press(&mut self, key: StenoKey, pressed: bool) {
+        if pressed {
+            self.chord |= 1 << key as u8;
+            self.held += 1;
+            return;
+        }
+
+        self.held = self.held.saturating_sub(1);
+        if self.held > 0 || self.chord == 0 {
+            return;
+        }
+
+        self.typing |= self.chord;
+        self.chord = 0;
+    }
+
+    /// Keys to hold down in the current report.
+    ///
+    /// Like macros, the next group is only sent once the previous report was
+    /// sent.
+    pub fn keys(&mut self, report_sent: bool) -> &[KeyCode] {
+        if report_sent {
+            if self.typing != 0 {
+                self.next_group();
+            } else {
+                self.group_len = 0;
+            }
+        }
+        &self.group[..self.group_len]
+    }
+
+    fn next_group(&mut self) {
+        // keep the last key of the previous group held
+        let mut len = 0;
+        if self.group_len > 0 {
+            self.group[0] = self.group[self.group_len - 1];
+            len = 1;
+        }
+        for i in 0..STENO_KEYS {
+            if len == GROUP_SIZE {
+                break;
+            }
+            if self.typing & (1 << i) != 0 {
+                self.typing &= !(1 << i);
+                if KEYS[i] != KeyCode::No {
+                    self.group[len] = KEYS[i];
+                    len += 1;
+                }
+            }
+        }
+        self.group_len = len;
+    }
+}
@@ -0,0 +1,104 @@
+use action::Action;
+use layout::{TAP_DANCES, TAP_DANCE_COUNT};
+
+const MAX_TAPS: u8 = 3;
+
+/// Actions for a single, double and triple tap of a tap dance key.
+///
+/// If the key is still held on the last tap the action stays pressed
+/// until the key is released, otherwise it's tapped.
+pub struct TapDance {
+    pub actions: [Action; MAX_TAPS as usize],
+}
+
+#[derive(Copy, Clone)]
+enum State {
+    Idle,
+    Counting {
+        taps: u8,
+        pressed: bool,
+        since: u32,
+        term: u32,
+    },
+    Holding(Action),
+    Releasing(Action),
+}
+
+pub struct TapDances {
+    states: [State; TAP_DANCE_COUNT],
+}
+
+impl TapDances {
+    pub const fn new() -> TapDances {
+        TapDances {
+            states: [State::Idle; TAP_DANCE_COUNT],
+        }
+    }
+
+    /// Feeds a press or release of tap dance key `id` into its state machine.
+    ///
+    /// Taps have to follow each other within `term` scans.
+    pub fn process(&mut self, id: u8, pressed: bool, changed: bool, now: u32, term: u32) {
+        let id = id as usize;
+        if !changed || id >= TAP_DANCE_COUNT {
+            return;
+        }
+
+        let state = self.states[id];
+        self.states[id] = match (state, pressed) {
+            (State::Counting { taps, .. }, true) => State::Counting {
+                taps: if taps < MAX_TAPS { taps + 1 } else { taps },
+                pressed: true,
+                since: now,
+                term,
+            },
+            (State::Counting { taps, .. }, false) => State::Counting {
+                taps,
+                pressed: false,
+                since: now,
+                term,
+            },
+            (State::Holding(action), false) => State::Releasing(action),
+            (_, true) => State::Counting {
+                taps: 1,
+                pressed: true,
+                since: now,
+                term,
+            },
+            (state, false) => state,
+        };
+    }
+
+    /// Advances the state machine of tap dance `id` and returns the
+    /// (action, pressed, changed) event it produces in this scan.
+    pub fn poll(&mut self, id: usize, now: u32) -> Option<(Action, bool, bool)> {
+        let state = self.states[id];
+        match state {
+            State::Idle => None,
+            State::Counting {
+                taps,
+                pressed,
+                since,
+                term,
+            } => {
+                let timed_out = now.wrapping_sub(since) >= term;
+                if timed_out || (!pressed && taps == MAX_TAPS) {
+                    let action = TAP_DANCES[id].actions[taps as usize - 1];
+                    self.states[id] = if pressed {
+                        State::Holding(action)
+                    } else {
+                        State::Releasing(action)
+                    };
+                    Some((action, true, true))
+                } else {
+                    None
+                }
+            }
+            State::Holding(action) => Some((action, true, false)),
+            State::Releasing(action) => {
+                self.states[id] = State::Idle;
+                Some((action, false, true))
+            }
+        }
+    }
+}
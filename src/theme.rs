@@ -0,0 +1,78 @@
+use core::cmp::min;
+use framebuffer::{Rgb, KEYS};
+use gamma::Gamma;
+
+/// Colors of every key, in KeyIndex order.
+pub type ThemeFrame = [Rgb; KEYS];
+
+// theme bytes per UserStaticTheme message, the block header comes on top
+const BLOCK_SIZE: usize = 96;
+pub const MAX_BLOCK_LEN: usize = 3 + BLOCK_SIZE;
+
+/// A user theme on its way into one of the LED controller's theme slots.
+///
+/// The theme is sent as [slot, number of frames] followed by the r, g, b of
+/// every key of every frame, split into blocks of
+/// [data length, number of blocks, block index, data...].
+pub struct ThemeUpload {
+    slot: u8,
+    frames: &'static [ThemeFrame],
+    block: usize,
+}
+
+impl ThemeUpload {
+    pub fn new(slot: u8, frames: &'static [ThemeFrame]) -> ThemeUpload {
+        ThemeUpload {
+            slot,
+            frames,
+            block: 0,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.block >= self.blocks()
+    }
+
+    /// Writes the next block into `payload`, with `gamma` applied to the
+    /// colors, and returns its length.
+    pub fn encode(&self, payload: &mut [u8], gamma: Gamma) -> usize {
+        let start = self.block * BLOCK_SIZE;
+        let end = min(start + BLOCK_SIZE, self.len());
+        payload[0] = (end - start) as u8;
+        payload[1] = self.blocks() as u8;
+        payload[2] = self.block as u8;
+        for (i, offset) in (start..end).enumerate() {
+            payload[3 + i] = self.byte(offset, gamma);
+        }
+        3 + end - start
+    }
+
+    pub fn mark_sent(&mut self) {
+        self.block += 1;
+    }
+
+    fn len(&self) -> usize {
+        2 + self.frames.len() * KEYS * 3
+    }
+
+    fn blocks(&self) -> usize {
+        (self.len() + BLOCK_SIZE - 1) / BLOCK_SIZE
+    }
+
+    fn byte(&self, offset: usize, gamma: Gamma) -> u8 {
+        match offset {
+            0 => self.slot,
+            1 => self.frames.len() as u8,
+            _ => {
+                let offset = offset - 2;
+                let color = self.frames[offset / (KEYS * 3)][offset / 3 % KEYS];
+                let Rgb(r, g, b) = gamma.apply(color);
+                match offset % 3 {
+                    0 => r,
+                    1 => g,
+                    _ => b,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use action::Action;
+use keycodes::KeyCode;
+use layout::UNICODE;
+
+/// How the host OS takes Unicode input.
+#[derive(Copy, Clone, PartialEq)]
+pub enum UnicodeMode {
+    /// Ctrl+Shift+U, code point, Space (IBus/GTK)
+    Linux,
+    /// code point typed while holding Option, needs the Unicode Hex Input
+    /// keyboard layout and only supports the Basic Multilingual Plane
+    MacOs,
+    /// compose key (right Alt), U, code point, Enter
+    WinCompose,
+}
+
+// modifier bits of LCtrl + LShift and LAlt
+const CTRL_SHIFT: u8 = 0b0000_0011;
+const ALT: u8 = 0b0000_0100;
+
+const LINUX_PREFIX: &[(KeyCode, u8)] = &[(KeyCode::U, CTRL_SHIFT)];
+const LINUX_SUFFIX: &[(KeyCode, u8)] = &[(KeyCode::Space, 0)];
+const WINCOMPOSE_PREFIX: &[(KeyCode, u8)] = &[(KeyCode::RAlt, 0), (KeyCode::U, 0)];
+const WINCOMPOSE_SUFFIX: &[(KeyCode, u8)] = &[(KeyCode::Enter, 0)];
+
+const HEX_DIGITS: [KeyCode; 16] = [
+    KeyCode::N0,
+    KeyCode::N1,
+    KeyCode::N2,
+    KeyCode::N3,
+    KeyCode::N4,
+    KeyCode::N5,
+    KeyCode::N6,
+    KeyCode::N7,
+    KeyCode::N8,
+    KeyCode::N9,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+];
+
+/// Types characters from layout::UNICODE with the host's input method, one
+/// key per scan.
+pub struct UnicodeInput {
+    pub mode: UnicodeMode,
+    typing: Option<u32>,
+    index: usize,
+    releasing: bool,
+    modifiers: u8,
+    last: Option<(Action, bool, bool)>,
+}
+
+impl UnicodeInput {
+    pub const fn new() -> UnicodeInput {
+        UnicodeInput {
+            mode: UnicodeMode::Linux,
+            typing: None,
+            index: 0,
+            releasing: false,
+            modifiers: 0,
+            last: None,
+        }
+    }
+
+    pub fn type_char(&mut self, id: u8) {
+        if let Some(c) = UNICODE.get(id as usize) {
+            self.type_code_point(*c);
+        }
+    }
+
+    pub fn type_code_point(&mut self, c: char) {
+        self.typing = Some(c as u32);
+        self.index = 0;
+        self.releasing = false;
+    }
+
+    /// Modifiers to hold down in the current report.
+    pub fn modifiers(&self) -> u8 {
+        self.modifiers
+    }
+
+    /// Key and modifiers of step `index` of the sequence for `code_point`.
+    fn step(&self, code_point: u32, index: usize) -> Option<(KeyCode, u8)> {
+        let (prefix, suffix, digit_modifiers) = match self.mode {
+            UnicodeMode::Linux => (LINUX_PREFIX, LINUX_SUFFIX, 0),
+            UnicodeMode::MacOs => (&[][..], &[][..], ALT),
+            UnicodeMode::WinCompose => (WINCOMPOSE_PREFIX, WINCOMPOSE_SUFFIX, 0),
+        };
+
+        let mut digits = 4;
+        while digits < 8 && code_point >> (digits * 4) != 0 {
+            digits += 1;
+        }
+
+        if index < prefix.len() {
+            return Some(prefix[index]);
+        }
+        let index = index - prefix.len();
+        if index < digits {
+            let nibble = (code_point >> ((digits - 1 - index) * 4)) & 0xf;
+            return Some((HEX_DIGITS[nibble as usize], digit_modifiers));
+        }
+        suffix.get(index - digits).cloned()
+    }
+
+    /// Returns the (action, pressed, changed) event for this scan.
+    ///
+    /// Like macros, the next key is only sent once the previous report was
+    /// sent.
+    pub fn poll(&mut self, report_sent: bool) -> Option<(Action, bool, bool)> {
+        if !report_sent && self.last.is_some() {
+            return self.last;
+        }
+
+        let code_point = match self.typing {
+            Some(code_point) => code_point,
+            None => return None,
+        };
+        let index = self.index;
+        match self.step(code_point, index) {
+            Some((key, modifiers)) => {
+                let pressed = !self.releasing;
+                if self.releasing {
+                    self.index += 1;
+                }
+                self.releasing = !self.releasing;
+                self.modifiers = modifiers;
+                self.last = Some((Action::Key(key), pressed, true));
+            }
+            None => {
+                self.typing = None;
+                self.modifiers = 0;
+                self.last = None;
+            }
+        }
+        self.last
+    }
+}
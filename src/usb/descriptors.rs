@@ -41,7 +41,7 @@ pub const CONF_DESC: [u8; 34] = [
     0x00,        // bCountryCode
     0x01,        // bNumDescriptors
     0x22,        // bDescriptorType[0] (HID)
-    0x29, 0x00,  // wDescriptorLength[0] 41
+    0x2a, 0x00,  // wDescriptorLength[0] 42
 
     0x07,        // bLength
     0x05,        // bDescriptorType (Endpoint)
@@ -51,7 +51,7 @@ pub const CONF_DESC: [u8; 34] = [
     0x01,        // bInterval 1 (unit depends on device speed)
 ];
 
-pub const HID_REPORT_DESC: [u8; 41] = [
+pub const HID_REPORT_DESC: [u8; 42] = [
     0x05, 0x01, // Usage Page: Generic Desktop Controls
     0x09, 0x06, // Usage: Keyboard
     0xa1, 0x01, // Collection: Application
@@ -67,10 +67,10 @@ pub const HID_REPORT_DESC: [u8; 41] = [
     0x95, 0x03, //   Report Count (3)
     0x75, 0x08, //   Report Size (8)
     0x15, 0x00, //   Logical Minimum (0)
-    0x25, 0x64, //   Logical Maximum (100)
+    0x26, 0x98, 0x00, //   Logical Maximum (0x98)
     0x05, 0x07, //   Usage Page (Kbrd/Keypad)
     0x19, 0x00, //   Usage Minimum (0x00)
-    0x29, 0x65, //   Usage Maximum (0x65)
+    0x29, 0x98, //   Usage Maximum (0x98, Lang9)
     0x81, 0x00, //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
     0xC0,       // End Collection
 ];